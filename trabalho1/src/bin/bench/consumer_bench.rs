@@ -0,0 +1,85 @@
+use rand::Rng;
+use sd::consumer::{Backend, Consumer};
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Each backend gets its own port so the consumers can stay alive side by side.
+const BASE_PORT: u16 = 31400;
+
+fn main() {
+    // Usage: consumer_bench [producers] [numbers per producer] [backend...]
+    let args = env::args().collect::<Vec<_>>();
+    let producers = args
+        .get(1)
+        .map(|s| usize::from_str(s).expect("failed to parse how many producers to run"))
+        .unwrap_or(8);
+    let numbers = args
+        .get(2)
+        .map(|s| usize::from_str(s).expect("failed to parse how many numbers to send"))
+        .unwrap_or(10_000);
+    let mut backends = args[3.min(args.len())..]
+        .iter()
+        .map(|s| Backend::from_str(s).expect("invalid backend"))
+        .collect::<Vec<_>>();
+    if backends.is_empty() {
+        backends = vec![Backend::Blocking, Backend::Epoll, Backend::IoUring];
+    }
+
+    println!("backend,producers,numbers,time_ms,numbers_per_sec");
+    for (i, backend) in backends.into_iter().enumerate() {
+        let addr = format!("127.0.0.1:{}", BASE_PORT + i as u16);
+        let mut consumer = Consumer::from_socket(addr.clone())
+            .expect("failed to create consumer")
+            .with_backend(backend);
+        consumer.set_verbose(false);
+        // The consumer never returns, it dies with the benchmark.
+        thread::spawn(move || consumer.read().expect("consumer failed"));
+        thread::sleep(Duration::from_millis(100));
+
+        let elapsed = run_producers(&addr, producers, numbers);
+        let total = producers * numbers;
+        println!(
+            "{:?},{},{},{},{:.0}",
+            backend,
+            producers,
+            total,
+            elapsed.as_millis(),
+            total as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
+
+// Connects `producers` clients that each send `numbers` integers, waiting for
+// every answer before sending the next one, like `Producer` does.
+fn run_producers(addr: &str, producers: usize, numbers: usize) -> Duration {
+    let start = Instant::now();
+    let handles = (0..producers)
+        .map(|_| {
+            let addr = addr.to_string();
+            thread::spawn(move || {
+                let mut stream = TcpStream::connect(addr).expect("failed to connect");
+                stream.set_nodelay(true).unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut rng = rand::thread_rng();
+                let mut int: i32 = 1;
+                let mut answer = String::new();
+                for _ in 0..numbers {
+                    int += rng.gen_range(1..101);
+                    writeln!(stream, "{}", int).expect("failed to send number");
+                    answer.clear();
+                    reader
+                        .read_line(&mut answer)
+                        .expect("failed to read answer");
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
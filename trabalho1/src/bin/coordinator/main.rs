@@ -0,0 +1,45 @@
+use sd::coordinator::Coordinator;
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+fn main() {
+    // Usage: coordinator START END WORKER... [--chunk N] [--timeout SECONDS]
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() < 4 {
+        println!("Please specify the range to search and at least one worker address.");
+        std::process::exit(1);
+    }
+    let start = u64::from_str(&args[1]).expect("failed to parse the start of the range.");
+    let end = u64::from_str(&args[2]).expect("failed to parse the end of the range.");
+
+    let mut workers = vec![];
+    let mut coordinator_args = args[3..].iter();
+    let mut chunk_size = None;
+    let mut timeout = None;
+    while let Some(arg) = coordinator_args.next() {
+        match arg.as_str() {
+            "--chunk" => chunk_size = coordinator_args.next(),
+            "--timeout" => timeout = coordinator_args.next(),
+            worker => workers.push(worker.to_string()),
+        }
+    }
+
+    let mut coordinator = Coordinator::new(workers);
+    if let Some(chunk_size) = chunk_size {
+        coordinator =
+            coordinator.with_chunk_size(u64::from_str(chunk_size).expect("invalid chunk size"));
+    }
+    if let Some(timeout) = timeout {
+        coordinator = coordinator.with_timeout(Duration::from_secs_f64(
+            f64::from_str(timeout).expect("invalid timeout"),
+        ));
+    }
+    match coordinator.run(start, end) {
+        Ok(report) => print!("{}", report),
+        Err(e) => {
+            println!("prime search failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
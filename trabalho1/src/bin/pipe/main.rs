@@ -3,17 +3,31 @@ use std::str::FromStr;
 
 use nix::unistd::pipe;
 use nix::unistd::{fork, ForkResult, Pid};
+use num_bigint::BigUint;
+use num_traits::One;
 use sd::consumer::Consumer;
 use sd::producer::Producer;
+use sd::results::ResultFormat;
+
+const USAGE: &str = "usage: pipe COUNT [--start N] [--results FILE] [--format csv|jsonl|bin]";
 
 fn main() {
     let args = env::args().collect::<Vec<_>>();
-    if args.len() < 2 {
-        println!("Please specify how many numbers to generate.");
-        std::process::exit(1);
-    }
-    let number_count =
-        i32::from_str(&args[1]).expect("failed to parse how many numbers should be produced.");
+    let number_count = match args.get(1).map(|count| u64::from_str(count)) {
+        Some(Ok(count)) => count,
+        _ => usage("please specify how many numbers to generate."),
+    };
+    let start = match flag(&args, "--start").map(BigUint::from_str) {
+        Some(Ok(start)) => start,
+        Some(Err(_)) => usage("failed to parse the starting number."),
+        None => BigUint::one(),
+    };
+    let format = match flag(&args, "--format").map(ResultFormat::from_str) {
+        Some(Ok(format)) => Some(format),
+        Some(Err(_)) => usage("unknown result format."),
+        None => None,
+    };
+    let results = flag(&args, "--results");
 
     let (stdin, stdout) = pipe().expect("failed to create pipe");
 
@@ -26,11 +40,17 @@ fn main() {
             );
             let mut producer = Producer::from_fd(stdout).expect("failed to create producer");
             producer
-                .produce_random_ints(number_count)
+                .produce_random_ints_from(start, number_count)
                 .expect("failed to produce ints");
         }
         Ok(ForkResult::Child) => {
             let mut consumer = Consumer::from_fd(stdin).expect("failed to create consumer");
+            if let Some(path) = results {
+                let format = format.unwrap_or_else(|| ResultFormat::from_path(path));
+                consumer = consumer
+                    .with_results(path, format)
+                    .expect("failed to open results file");
+            }
             loop {
                 consumer.read().expect("failed to read");
             }
@@ -38,3 +58,15 @@ fn main() {
         Err(_) => println!("failed to fork"),
     }
 }
+
+fn usage(problem: &str) -> ! {
+    eprintln!("{}\n{}", problem, USAGE);
+    std::process::exit(1);
+}
+
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|value| value.as_str())
+}
@@ -0,0 +1,12 @@
+use sd::results::ResultSummary;
+use std::env;
+
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() < 2 {
+        println!("Please specify which results file should be summarized.");
+        std::process::exit(1);
+    }
+    let summary = ResultSummary::from_file(&args[1]).expect("failed to read results file");
+    print!("{}", summary);
+}
@@ -1,7 +1,49 @@
-use sd::consumer::Consumer;
+use sd::consumer::{Backend, Consumer};
+use sd::results::ResultFormat;
+use std::env;
+use std::str::FromStr;
 
 fn main() {
-    let mut consumer =
-        Consumer::from_socket("127.0.0.1:31337".to_string()).expect("failed to create consumer");
-    let _ = consumer.read().unwrap();
+    // Usage: socket_consumer [--addr ADDR] [--backend blocking|epoll|io_uring]
+    //                        [--results FILE] [--format csv|jsonl|bin] [--metrics ADDR]
+    //                        [--config FILE]
+    // Signals: SIGHUP reloads --config, SIGUSR1 prints statistics,
+    // SIGUSR2 toggles verbose output, SIGTERM drains and exits.
+    let args = env::args().collect::<Vec<_>>();
+    let addr = flag(&args, "--addr").unwrap_or("127.0.0.1:31337");
+    let mut consumer = Consumer::from_socket(addr.to_string()).expect("failed to create consumer");
+    if let Some(backend) = flag(&args, "--backend") {
+        consumer = consumer.with_backend(Backend::from_str(backend).expect("invalid backend"));
+    }
+    if let Some(path) = flag(&args, "--results") {
+        let format = match flag(&args, "--format") {
+            Some(format) => ResultFormat::from_str(format).expect("invalid result format"),
+            None => ResultFormat::from_path(path),
+        };
+        consumer = consumer
+            .with_results(path, format)
+            .expect("failed to open results file");
+    }
+    let config = flag(&args, "--config");
+    if let Some(path) = config {
+        consumer = consumer
+            .with_config(path)
+            .expect("failed to load configuration");
+    }
+    consumer = consumer
+        .with_signal_control(config)
+        .expect("failed to install signal handlers");
+    if let Some(addr) = flag(&args, "--metrics") {
+        consumer = consumer
+            .with_metrics(addr)
+            .expect("failed to start metrics endpoint");
+    }
+    consumer.read().unwrap();
+}
+
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|value| value.as_str())
 }
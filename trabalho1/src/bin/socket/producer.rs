@@ -1,3 +1,5 @@
+use num_bigint::BigUint;
+use num_traits::One;
 use sd::producer::Producer;
 use std::env;
 use std::str::FromStr;
@@ -9,9 +11,16 @@ fn main() {
         std::process::exit(1);
     }
     let number_count =
-        i32::from_str(&args[1]).expect("failed to parse how many numbers should be produced.");
+        u64::from_str(&args[1]).expect("failed to parse how many numbers should be produced.");
+    // The walk may start anywhere, e.g. 340282366920938463463374607431768211297.
+    let start = match args.get(2) {
+        Some(start) => BigUint::from_str(start).expect("failed to parse the starting number."),
+        None => BigUint::one(),
+    };
 
     let mut producer =
         Producer::from_socket("127.0.0.1:31337".to_string()).expect("failed to create producer");
-    producer.produce_random_ints(number_count).unwrap();
+    producer
+        .produce_random_ints_from(start, number_count)
+        .unwrap();
 }
@@ -1,12 +1,79 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::os::unix::io::{FromRawFd, RawFd};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use num_bigint::BigUint;
+use num_traits::Zero;
+
+use crate::control::{self, ConsumerConfig};
+use crate::event_loop;
+use crate::metrics::Metrics;
+use crate::primality::is_prime;
+use crate::protocol::read_number;
+use crate::results::{ResultFormat, ResultSink, Verdict};
+use crate::uring;
+
+// Most numbers a single `range` request may ask for. Ranges are counted on
+// the thread serving the connection, which for the epoll and io_uring
+// backends is serving every other producer too.
+pub const MAX_RANGE: u64 = 1_000_000;
+
+// How a socket consumer waits on its producers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    // One blocking thread per producer connection.
+    Blocking,
+    // A single thread multiplexing every connection with epoll.
+    Epoll,
+    // A single thread batching accepts, reads and writes through io_uring.
+    IoUring,
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "blocking" => Ok(Self::Blocking),
+            "epoll" => Ok(Self::Epoll),
+            "io_uring" | "iouring" | "uring" => Ok(Self::IoUring),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown backend '{}', use blocking, epoll or io_uring", s),
+            )),
+        }
+    }
+}
 
 pub struct Consumer {
-    reader: Option<Box<dyn Read>>, //std::io::Write>,
+    reader: Option<Box<dyn Read + Send>>, //std::io::Write>,
     listener: Option<TcpListener>,
+    producer: String,
+    backend: Backend,
+    context: Context,
+}
+
+// State shared by every connection handled by the consumer.
+#[derive(Clone)]
+pub(crate) struct Context {
+    pub(crate) sink: Arc<Mutex<Option<ResultSink>>>,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) verbose: Arc<AtomicBool>,
+    // Set on SIGTERM: new producers are turned away while open ones finish.
+    pub(crate) draining: Arc<AtomicBool>,
+}
+
+// What should be sent back to a producer after one of its lines.
+pub(crate) enum Reply {
+    Answer(String),
+    // The producer sent 0: the message must be delivered and the consumer stopped.
+    Finish(String),
 }
 
 impl Consumer {
@@ -16,76 +83,243 @@ impl Consumer {
         Ok(Self {
             reader: None,
             listener: Some(listener),
+            producer: String::new(),
+            backend: Backend::Blocking,
+            context: Context::new(),
         })
     }
 
     pub fn from_fd(fd: RawFd) -> Result<Self, Error> {
         Ok(Self {
-            reader: Some(unsafe { Box::new(File::from_raw_fd(fd)) }),
+            reader: Some(unsafe { Box::new(BufReader::new(File::from_raw_fd(fd))) }),
             listener: None,
+            producer: format!("fd:{}", fd),
+            backend: Backend::Blocking,
+            context: Context::new(),
         })
     }
 
+    // Every verdict will also be appended to the file at `path`.
+    pub fn with_results(self, path: &str, format: ResultFormat) -> Result<Self, Error> {
+        *self.context.sink.lock().unwrap() = Some(ResultSink::create(path, format)?);
+        Ok(self)
+    }
+
+    // Exposes Prometheus metrics over HTTP at `addr`.
+    pub fn with_metrics(self, addr: &str) -> Result<Self, Error> {
+        Arc::clone(&self.context.metrics).serve(addr)?;
+        Ok(self)
+    }
+
+    // Applies the settings in the configuration file at `path`.
+    pub fn with_config(self, path: &str) -> Result<Self, Error> {
+        self.context.apply(&ConsumerConfig::from_file(path)?)?;
+        Ok(self)
+    }
+
+    // Lets the consumer be controlled with signals: SIGHUP reloads the
+    // configuration file, SIGUSR1 prints statistics, SIGUSR2 toggles verbose
+    // output and SIGTERM stops it once the open connections are done.
+    pub fn with_signal_control(self, config_path: Option<&str>) -> Result<Self, Error> {
+        control::start(self.context.clone(), config_path.map(String::from))?;
+        Ok(self)
+    }
+
+    // Selects how socket connections are served. Has no effect on fd consumers.
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    // Whether every verdict is printed to stdout. Enabled by default.
+    pub fn set_verbose(&self, verbose: bool) {
+        self.context.verbose.store(verbose, Ordering::Relaxed);
+    }
+
+    // Address the socket consumer is listening on, useful when bound to port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.as_ref()?.local_addr().ok()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.context.metrics)
+    }
+
     pub fn read(&mut self) -> Result<(), Error> {
         if let Some(reader) = &mut self.reader {
-            let mut buffer = [0; 4];
-            let _ = reader.read(&mut buffer)?;
-            let int = i32::from_be_bytes(buffer);
-            if int == 0 {
+            // A closed pipe ends the consumer just like a 0 does.
+            let int = read_number(reader)?.unwrap_or_default();
+            if int.is_zero() {
                 finish_consumer()
             }
-            if is_prime(int) {
-                println!("{} is prime", int);
+            let verdict = self.context.evaluate(&self.producer, &int);
+            if verdict.is_prime {
+                self.context.log(&format!("{} is prime\n", int));
             } else {
-                println!("{} is not prime", int);
+                self.context.log(&format!("{} is not prime\n", int));
             };
         }
         if let Some(listener) = &mut self.listener {
-            for stream in listener.incoming() {
-                process_stream(&mut stream?);
+            match self.backend {
+                Backend::Blocking => {
+                    for stream in listener.incoming() {
+                        let mut stream = stream?;
+                        if self.context.is_draining() {
+                            continue;
+                        }
+                        let context = self.context.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = process_stream(&mut stream, &context) {
+                                eprintln!("connection lost: {}", e);
+                            }
+                        });
+                    }
+                }
+                Backend::Epoll => event_loop::run(listener, &self.context)?,
+                Backend::IoUring => uring::run(listener, &self.context)?,
             }
         }
         Ok(())
     }
 }
 
-fn process_stream(stream: &mut TcpStream) {
-    let reader = BufReader::new(stream.try_clone().unwrap());
-    for line in reader.lines() {
-        let message = line.unwrap();
-        let int = i32::from_str(&message).unwrap();
-        if int == 0 {
-            let _ = write!(stream, "finishing consumer when 0 is consumed.\n");
-            finish_consumer();
+impl Context {
+    fn new() -> Self {
+        Self {
+            sink: Arc::new(Mutex::new(None)),
+            metrics: Arc::new(Metrics::new()),
+            verbose: Arc::new(AtomicBool::new(true)),
+            draining: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    // Runs the primality test, measuring how long it took, and reports the
+    // verdict to the results file and metrics.
+    fn evaluate(&self, producer: &str, int: &BigUint) -> Verdict {
+        let start = Instant::now();
+        let prime = is_prime(int);
+        let verdict = Verdict::new(producer, int, prime, start.elapsed());
+        self.metrics
+            .observe(producer, verdict.is_prime, verdict.elapsed);
+        if let Some(sink) = self.sink.lock().unwrap().as_mut() {
+            if let Err(e) = sink.record(&verdict) {
+                eprintln!("failed to record result: {}", e);
+            }
+        }
+        verdict
+    }
+
+    // Handles a single line of the socket protocol.
+    pub(crate) fn reply(&self, producer: &str, line: &str) -> Reply {
+        if let Some(range) = line.trim().strip_prefix("range ") {
+            return Reply::Answer(self.count_range(producer, range));
         }
-        if is_prime(int) {
-            let answer = format!("{} is prime\n", int);
-            send_answer(stream, answer)
+        let int = match BigUint::from_str(line.trim()) {
+            Ok(int) => int,
+            Err(_) => return Reply::Answer(format!("{} is not a number\n", line.trim())),
+        };
+        if int.is_zero() {
+            return Reply::Finish("finishing consumer when 0 is consumed.\n".to_string());
+        }
+        let answer = if self.evaluate(producer, &int).is_prime {
+            format!("{} is prime\n", int)
         } else {
-            let answer = format!("{} is not prime\n", int);
-            send_answer(stream, answer)
+            format!("{} is not prime\n", int)
+        };
+        self.log(&answer);
+        Reply::Answer(answer)
+    }
+
+    // Answers `range <start> <end>` with the number of primes in [start, end),
+    // which is how the coordinator hands work to a consumer. Only the totals
+    // go to the metrics: a verdict per number would flood the results file.
+    fn count_range(&self, producer: &str, range: &str) -> String {
+        let bounds = range
+            .split_whitespace()
+            .map(BigUint::from_str)
+            .collect::<Result<Vec<_>, _>>();
+        let (start, end) = match bounds.as_deref() {
+            Ok([start, end]) => (start.clone(), end.clone()),
+            _ => return format!("range {} is not a valid range\n", range),
+        };
+        if end > start && &end - &start > BigUint::from(MAX_RANGE) {
+            return format!(
+                "range {} is too large, ask for at most {} numbers\n",
+                range, MAX_RANGE
+            );
+        }
+        let mut numbers = 0u64;
+        let mut primes = 0u64;
+        let mut int = start.clone();
+        while int < end {
+            numbers += 1;
+            if is_prime(&int) {
+                primes += 1;
+            }
+            int += 1u32;
+        }
+        self.metrics.observe_batch(producer, numbers, primes);
+        let answer = format!("range {} {} {}\n", start, end, primes);
+        self.log(&answer);
+        answer
+    }
+
+    fn log(&self, message: &str) {
+        if self.verbose.load(Ordering::Relaxed) {
+            print!("{}", message);
         }
     }
 }
 
-fn send_answer(stream: &mut TcpStream, answer: String) {
-    print!("{}", answer);
-    let _ = stream
-        .write_all(answer.as_bytes())
-        .expect("failed to send answer");
-    let _ = stream.flush();
+fn process_stream(stream: &mut TcpStream, context: &Context) -> Result<(), Error> {
+    let producer = stream
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    let connection = OpenConnection::new(&context.metrics, producer);
+    let reader = BufReader::new(stream.try_clone()?);
+    for line in reader.lines() {
+        match context.reply(&connection.producer, &line?) {
+            Reply::Answer(answer) => send_answer(stream, answer)?,
+            Reply::Finish(message) => {
+                let _ = stream.write_all(message.as_bytes());
+                finish_consumer();
+            }
+        }
+    }
+    Ok(())
 }
 
-fn finish_consumer() {
-    println!("Received 0. Ending consumer.");
-    std::process::exit(0);
+fn send_answer(stream: &mut TcpStream, answer: String) -> Result<(), Error> {
+    stream.write_all(answer.as_bytes())?;
+    stream.flush()
 }
 
-fn is_prime(int: i32) -> bool {
-    for den in 2..(int / 2) {
-        if int % den == 0 {
-            return false;
-        }
+// Counts a connection as open in the metrics until it is dropped, so the
+// gauge goes back down however its thread leaves.
+struct OpenConnection<'a> {
+    metrics: &'a Metrics,
+    producer: String,
+}
+
+impl<'a> OpenConnection<'a> {
+    fn new(metrics: &'a Metrics, producer: String) -> Self {
+        metrics.connection_opened(&producer);
+        Self { metrics, producer }
+    }
+}
+
+impl Drop for OpenConnection<'_> {
+    fn drop(&mut self) {
+        self.metrics.connection_closed(&self.producer);
     }
-    true
+}
+
+pub(crate) fn finish_consumer() {
+    println!("Received 0. Ending consumer.");
+    std::process::exit(0);
 }
@@ -0,0 +1,218 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::libc;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet};
+
+use crate::consumer::Context;
+use crate::results::{ResultFormat, ResultSink};
+
+// How often the control thread looks for signals that arrived.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// How long open connections may keep going after a SIGTERM.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Signal handlers may only touch atomics, the actual work happens in the
+// control thread.
+static RELOAD: AtomicBool = AtomicBool::new(false);
+static DUMP_STATS: AtomicBool = AtomicBool::new(false);
+static TOGGLE_VERBOSE: AtomicBool = AtomicBool::new(false);
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+
+// Settings that can be changed while the consumer runs. Read from a file
+// with one `key = value` pair per line; `#` starts a comment.
+#[derive(Debug, Default, PartialEq)]
+pub struct ConsumerConfig {
+    pub verbose: Option<bool>,
+    pub results: Option<String>,
+    pub format: Option<ResultFormat>,
+}
+
+impl ConsumerConfig {
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        Self::from_str(&fs::read_to_string(path)?)
+    }
+}
+
+impl FromStr for ConsumerConfig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("expected key = value: {}", line),
+                )
+            })?;
+            let value = value.trim();
+            match key.trim() {
+                "verbose" => {
+                    config.verbose = Some(bool::from_str(value).map_err(|e| {
+                        Error::new(ErrorKind::InvalidData, format!("verbose: {}", e))
+                    })?)
+                }
+                "results" => config.results = Some(value.to_string()),
+                "format" => config.format = Some(ResultFormat::from_str(value)?),
+                key => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("unknown configuration key '{}'", key),
+                    ))
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl Context {
+    // Settings missing from the configuration are left as they are.
+    pub(crate) fn apply(&self, config: &ConsumerConfig) -> Result<(), Error> {
+        if let Some(path) = &config.results {
+            let format = config
+                .format
+                .unwrap_or_else(|| ResultFormat::from_path(path));
+            *self.sink.lock().unwrap() = Some(ResultSink::create(path, format)?);
+        }
+        if let Some(verbose) = config.verbose {
+            self.verbose.store(verbose, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+// Installs the handlers and starts the thread that reacts to them:
+// SIGHUP reloads `config_path`, SIGUSR1 prints statistics, SIGUSR2 toggles
+// verbose output and SIGTERM drains the consumer before exiting.
+pub(crate) fn start(context: Context, config_path: Option<String>) -> Result<(), Error> {
+    let handlers = &[
+        (SigHandler::Handler(sighup_handler), signal::SIGHUP),
+        (SigHandler::Handler(sigusr1_handler), signal::SIGUSR1),
+        (SigHandler::Handler(sigusr2_handler), signal::SIGUSR2),
+        (SigHandler::Handler(sigterm_handler), signal::SIGTERM),
+    ];
+    for (sig_handler, signal) in handlers {
+        let sa = SigAction::new(*sig_handler, SaFlags::SA_RESTART, SigSet::empty());
+        unsafe {
+            signal::sigaction(*signal, &sa)?;
+        }
+    }
+
+    let started = Instant::now();
+    thread::spawn(move || loop {
+        thread::sleep(POLL_INTERVAL);
+        if RELOAD.swap(false, Ordering::SeqCst) {
+            reload(&context, config_path.as_deref());
+        }
+        if DUMP_STATS.swap(false, Ordering::SeqCst) {
+            dump_stats(&context, started);
+        }
+        if TOGGLE_VERBOSE.swap(false, Ordering::SeqCst) {
+            let verbose = !context.verbose.fetch_xor(true, Ordering::Relaxed);
+            println!(
+                "[CONTROL] verbose output {}",
+                if verbose { "on" } else { "off" }
+            );
+        }
+        if TERMINATE.load(Ordering::SeqCst) {
+            drain(&context, started);
+        }
+    });
+    Ok(())
+}
+
+fn reload(context: &Context, config_path: Option<&str>) {
+    let path = match config_path {
+        Some(path) => path,
+        None => {
+            println!("[CONTROL] SIGHUP received but there is no configuration file to reload");
+            return;
+        }
+    };
+    match ConsumerConfig::from_file(path).and_then(|config| context.apply(&config)) {
+        Ok(()) => println!("[CONTROL] configuration reloaded from {}", path),
+        Err(e) => println!("[CONTROL] failed to reload {}: {}", path, e),
+    }
+}
+
+fn dump_stats(context: &Context, started: Instant) {
+    let metrics = &context.metrics;
+    let uptime = started.elapsed();
+    println!("[STATS] uptime: {:?}", uptime);
+    println!("[STATS] numbers processed: {}", metrics.numbers());
+    println!("[STATS] primes found: {}", metrics.primes());
+    println!(
+        "[STATS] numbers per second: {:.1}",
+        metrics.numbers() as f64 / uptime.as_secs_f64()
+    );
+    println!("[STATS] mean processing time: {:?}", metrics.mean_latency());
+    println!(
+        "[STATS] active connections: {}",
+        metrics.active_connections()
+    );
+}
+
+// Stops taking new producers and exits once the open connections are done
+// or `DRAIN_TIMEOUT` runs out.
+fn drain(context: &Context, started: Instant) -> ! {
+    println!("[CONTROL] SIGTERM received, draining connections");
+    context.draining.store(true, Ordering::SeqCst);
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    while context.metrics.active_connections() > 0 && Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+    }
+    let left = context.metrics.active_connections();
+    if left > 0 {
+        println!("[CONTROL] giving up on {} connections", left);
+    }
+    dump_stats(context, started);
+    // Results are flushed after every record, dropping the sink closes the file.
+    context.sink.lock().unwrap().take();
+    println!("[CONTROL] bye bye!");
+    std::process::exit(0)
+}
+
+extern "C" fn sighup_handler(_: libc::c_int) {
+    RELOAD.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn sigusr1_handler(_: libc::c_int) {
+    DUMP_STATS.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn sigusr2_handler(_: libc::c_int) {
+    TOGGLE_VERBOSE.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn sigterm_handler(_: libc::c_int) {
+    TERMINATE.store(true, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = ConsumerConfig::from_str(
+            "# consumer settings\nverbose = false\nresults = /tmp/results.bin  # binary log\n",
+        )
+        .unwrap();
+        assert_eq!(Some(false), config.verbose);
+        assert_eq!(Some("/tmp/results.bin".to_string()), config.results);
+        assert_eq!(None, config.format);
+
+        assert!(ConsumerConfig::from_str("verbose = maybe").is_err());
+        assert!(ConsumerConfig::from_str("port = 42").is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::consumer::MAX_RANGE;
+
+// A half-open range [start, end) of numbers to be checked by a worker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Chunk {
+    start: u64,
+    end: u64,
+}
+
+// Splits a range among several socket consumers and adds up how many primes
+// each of them found. Chunks held by a worker that fails or takes longer
+// than the timeout are handed to another worker.
+pub struct Coordinator {
+    workers: Vec<String>,
+    chunk_size: u64,
+    timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct WorkerReport {
+    pub chunks: usize,
+    pub primes: u64,
+    // Whether the worker was dropped before the job finished.
+    pub failed: bool,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub start: u64,
+    pub end: u64,
+    pub primes: u64,
+    pub chunks: usize,
+    pub reassigned: usize,
+    pub elapsed: Duration,
+    pub workers: BTreeMap<String, WorkerReport>,
+}
+
+// Bookkeeping shared by the threads talking to the workers.
+struct Job {
+    pending: VecDeque<Chunk>,
+    // Chunks currently assigned to some worker.
+    outstanding: usize,
+    primes: u64,
+    reassigned: usize,
+    workers: BTreeMap<String, WorkerReport>,
+}
+
+impl Coordinator {
+    pub fn new(workers: Vec<String>) -> Self {
+        Self {
+            workers,
+            chunk_size: 10_000,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    // Chunks are capped at what a consumer accepts in a single request.
+    pub fn with_chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size.clamp(1, MAX_RANGE);
+        self
+    }
+
+    // How long a worker may take to accept the connection or answer a
+    // single chunk.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Counts the primes in [start, end) using every worker.
+    pub fn run(&self, start: u64, end: u64) -> Result<Report, Error> {
+        let mut pending = VecDeque::new();
+        let mut chunk_start = start;
+        while chunk_start < end {
+            let chunk_end = chunk_start.saturating_add(self.chunk_size).min(end);
+            pending.push_back(Chunk {
+                start: chunk_start,
+                end: chunk_end,
+            });
+            chunk_start = chunk_end;
+        }
+        let chunks = pending.len();
+        let job = Arc::new((
+            Mutex::new(Job {
+                pending,
+                outstanding: 0,
+                primes: 0,
+                reassigned: 0,
+                workers: BTreeMap::new(),
+            }),
+            Condvar::new(),
+        ));
+
+        let now = Instant::now();
+        let handles = self
+            .workers
+            .iter()
+            .map(|addr| {
+                let job = Arc::clone(&job);
+                let addr = addr.clone();
+                let timeout = self.timeout;
+                thread::spawn(move || drive_worker(&addr, timeout, &job))
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            let _ = handle.join();
+        }
+
+        let mut job = job.0.lock().unwrap();
+        if !job.pending.is_empty() {
+            return Err(Error::other(format!(
+                "every worker failed with {} chunks left",
+                job.pending.len()
+            )));
+        }
+        Ok(Report {
+            start,
+            end,
+            primes: job.primes,
+            chunks,
+            reassigned: job.reassigned,
+            elapsed: now.elapsed(),
+            workers: std::mem::take(&mut job.workers),
+        })
+    }
+}
+
+// Feeds chunks to a single worker until there is nothing left or the worker
+// stops answering.
+fn drive_worker(addr: &str, timeout: Duration, job: &(Mutex<Job>, Condvar)) {
+    let (lock, cvar) = job;
+    lock.lock()
+        .unwrap()
+        .workers
+        .insert(addr.to_string(), WorkerReport::default());
+    let mut connection = connect(addr, timeout);
+    loop {
+        let chunk = {
+            let mut job = lock.lock().unwrap();
+            loop {
+                if connection.is_err() {
+                    break None;
+                }
+                if let Some(chunk) = job.pending.pop_front() {
+                    job.outstanding += 1;
+                    break Some(chunk);
+                }
+                // Another worker may still fail and give its chunk back.
+                if job.outstanding == 0 {
+                    break None;
+                }
+                job = cvar.wait(job).unwrap();
+            }
+        };
+        let (chunk, stream) = match (chunk, connection.as_mut()) {
+            (Some(chunk), Ok(stream)) => (chunk, stream),
+            _ => break,
+        };
+        let answer = count_primes(stream, chunk);
+
+        let mut job = lock.lock().unwrap();
+        job.outstanding -= 1;
+        match answer {
+            Ok(primes) => {
+                job.primes += primes;
+                let report = job.workers.get_mut(addr).unwrap();
+                report.chunks += 1;
+                report.primes += primes;
+            }
+            Err(e) => {
+                println!(
+                    "worker {} failed on [{}, {}): {}. Reassigning.",
+                    addr, chunk.start, chunk.end, e
+                );
+                job.pending.push_front(chunk);
+                job.reassigned += 1;
+                connection = Err(e);
+            }
+        }
+        cvar.notify_all();
+    }
+
+    let mut job = lock.lock().unwrap();
+    if connection.is_err() {
+        job.workers.get_mut(addr).unwrap().failed = true;
+    }
+    cvar.notify_all();
+}
+
+struct Connection {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+fn connect(addr: &str, timeout: Duration) -> Result<Connection, Error> {
+    let stream = connect_timeout(addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let reader = BufReader::new(stream.try_clone()?);
+    Ok(Connection { stream, reader })
+}
+
+// Tries every address `addr` resolves to, giving each one `timeout`.
+fn connect_timeout(addr: &str, timeout: Duration) -> Result<TcpStream, Error> {
+    let mut last_error = None;
+    for socket_addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} did not resolve to any address", addr),
+        )
+    }))
+}
+
+fn count_primes(connection: &mut Connection, chunk: Chunk) -> Result<u64, Error> {
+    writeln!(connection.stream, "range {} {}", chunk.start, chunk.end)?;
+    let mut answer = String::new();
+    if connection.reader.read_line(&mut answer)? == 0 {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "worker closed the connection",
+        ));
+    }
+    let expected = format!("range {} {} ", chunk.start, chunk.end);
+    answer
+        .trim_end()
+        .strip_prefix(&expected)
+        .and_then(|count| count.parse().ok())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("unexpected answer: {}", answer.trim_end()),
+            )
+        })
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} primes in [{}, {}) found in {:?}",
+            self.primes, self.start, self.end, self.elapsed
+        )?;
+        writeln!(f, "{} chunks, {} reassigned", self.chunks, self.reassigned)?;
+        for (addr, report) in &self.workers {
+            writeln!(
+                f,
+                "  {}: {} chunks, {} primes{}",
+                addr,
+                report.chunks,
+                report.primes,
+                if report.failed { " (failed)" } else { "" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::Consumer;
+    use std::net::TcpListener;
+
+    fn start_worker() -> String {
+        let mut consumer = Consumer::from_socket("127.0.0.1:0".to_string()).unwrap();
+        consumer.set_verbose(false);
+        let addr = consumer.local_addr().unwrap().to_string();
+        thread::spawn(move || consumer.read());
+        addr
+    }
+
+    #[test]
+    fn count_with_several_workers() {
+        let workers = vec![start_worker(), start_worker(), start_worker()];
+        let report = Coordinator::new(workers)
+            .with_chunk_size(500)
+            .run(0, 10_000)
+            .unwrap();
+        assert_eq!(1229, report.primes);
+        assert_eq!(20, report.chunks);
+        assert_eq!(0, report.reassigned);
+    }
+
+    #[test]
+    fn workers_refuse_oversized_ranges() {
+        let mut connection = connect(&start_worker(), Duration::from_secs(10)).unwrap();
+        let oversized = Chunk {
+            start: 0,
+            end: MAX_RANGE + 1,
+        };
+        let e = count_primes(&mut connection, oversized).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, e.kind());
+        let largest = Chunk {
+            start: 1,
+            end: MAX_RANGE + 1,
+        };
+        assert_eq!(78_498, count_primes(&mut connection, largest).unwrap());
+    }
+
+    #[test]
+    fn reassign_chunks_from_dead_workers() {
+        // Accepts connections and hangs up as soon as a chunk arrives.
+        let dead = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead_addr = dead.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in dead.incoming() {
+                let mut line = String::new();
+                let _ = BufReader::new(stream.unwrap()).read_line(&mut line);
+            }
+        });
+
+        let report = Coordinator::new(vec![dead_addr.clone(), start_worker()])
+            .with_chunk_size(100)
+            .run(0, 1_000)
+            .unwrap();
+        assert_eq!(168, report.primes);
+        assert!(report.workers[&dead_addr].failed);
+        assert_eq!(0, report.workers[&dead_addr].chunks);
+    }
+}
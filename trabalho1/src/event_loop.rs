@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use nix::errno::Errno;
+use nix::sys::epoll::{
+    epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+};
+
+use crate::consumer::{finish_consumer, Context, Reply};
+
+// Token used for the listening socket. Connections are numbered from 0.
+const LISTENER: u64 = u64::MAX;
+const MAX_EVENTS: usize = 1024;
+// Once this many answer bytes wait for a peer that is not reading them,
+// its requests are left in the socket until the backlog drains.
+const MAX_PENDING_OUTPUT: usize = 1 << 20;
+
+// A producer connection with the bytes still waiting to be parsed or sent.
+struct Connection {
+    stream: TcpStream,
+    producer: String,
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+    // The peer shut down its write side, only answers are left to send.
+    read_closed: bool,
+    // What epoll currently reports to us for this socket.
+    interest: EpollFlags,
+}
+
+// Serves every producer from the calling thread, using non-blocking sockets
+// multiplexed with epoll.
+pub(crate) fn run(listener: &TcpListener, context: &Context) -> Result<(), Error> {
+    listener.set_nonblocking(true)?;
+    let epoll = unsafe { OwnedFd::from_raw_fd(epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)?) };
+    let epfd = epoll.as_raw_fd();
+    register(
+        epfd,
+        EpollOp::EpollCtlAdd,
+        listener.as_raw_fd(),
+        LISTENER,
+        EpollFlags::EPOLLIN,
+    )?;
+
+    let mut connections: HashMap<u64, Connection> = HashMap::new();
+    let mut next_token = 0;
+    let mut events = vec![EpollEvent::empty(); MAX_EVENTS];
+    loop {
+        let ready = match epoll_wait(epfd, &mut events, -1) {
+            Ok(ready) => ready,
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for event in &events[..ready] {
+            let token = event.data();
+            if token == LISTENER {
+                accept_all(epfd, listener, context, &mut connections, &mut next_token)?;
+                continue;
+            }
+            let connection = match connections.get_mut(&token) {
+                Some(connection) => connection,
+                None => continue,
+            };
+            let open = serve(epfd, token, connection, event.events(), context);
+            if !open {
+                let connection = connections.remove(&token).unwrap();
+                let _ = epoll_ctl(
+                    epfd,
+                    EpollOp::EpollCtlDel,
+                    connection.stream.as_raw_fd(),
+                    None,
+                );
+                context.metrics.connection_closed(&connection.producer);
+            }
+        }
+    }
+}
+
+fn register(
+    epfd: RawFd,
+    op: EpollOp,
+    fd: RawFd,
+    token: u64,
+    flags: EpollFlags,
+) -> Result<(), Error> {
+    let mut event = EpollEvent::new(flags, token);
+    epoll_ctl(epfd, op, fd, &mut event)?;
+    Ok(())
+}
+
+// Reads until the peer shuts down its side, and waits for room in the
+// socket while there are answers left to send.
+fn interest(connection: &Connection) -> EpollFlags {
+    let mut flags = EpollFlags::empty();
+    if !connection.read_closed && connection.write_buffer.len() < MAX_PENDING_OUTPUT {
+        flags |= EpollFlags::EPOLLIN | EpollFlags::EPOLLRDHUP;
+    }
+    if !connection.write_buffer.is_empty() {
+        flags |= EpollFlags::EPOLLOUT;
+    }
+    flags
+}
+
+fn accept_all(
+    epfd: RawFd,
+    listener: &TcpListener,
+    context: &Context,
+    connections: &mut HashMap<u64, Connection>,
+    next_token: &mut u64,
+) -> Result<(), Error> {
+    loop {
+        let (stream, addr) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        // Dropping the stream turns the producer away.
+        if context.is_draining() {
+            continue;
+        }
+        stream.set_nonblocking(true)?;
+        let token = *next_token;
+        *next_token += 1;
+        let mut connection = Connection {
+            stream,
+            producer: addr.to_string(),
+            read_buffer: Vec::new(),
+            write_buffer: Vec::new(),
+            read_closed: false,
+            interest: EpollFlags::empty(),
+        };
+        connection.interest = interest(&connection);
+        register(
+            epfd,
+            EpollOp::EpollCtlAdd,
+            connection.stream.as_raw_fd(),
+            token,
+            connection.interest,
+        )?;
+        context.metrics.connection_opened(&connection.producer);
+        connections.insert(token, connection);
+    }
+}
+
+// Reads whatever is available, answers every complete line and flushes as
+// much as the socket accepts. Returns false once the connection is done.
+fn serve(
+    epfd: RawFd,
+    token: u64,
+    connection: &mut Connection,
+    flags: EpollFlags,
+    context: &Context,
+) -> bool {
+    let mut open = !flags.intersects(EpollFlags::EPOLLERR);
+    let readable = EpollFlags::EPOLLIN | EpollFlags::EPOLLRDHUP | EpollFlags::EPOLLHUP;
+    if !connection.read_closed && flags.intersects(readable) {
+        open &= fill(connection);
+        while let Some(end) = connection.read_buffer.iter().position(|b| *b == b'\n') {
+            let line = connection.read_buffer.drain(..=end).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            match context.reply(&connection.producer, &line) {
+                Reply::Answer(answer) => connection.write_buffer.extend(answer.as_bytes()),
+                Reply::Finish(message) => {
+                    connection.write_buffer.extend(message.as_bytes());
+                    let _ = connection.stream.set_nonblocking(false);
+                    let _ = connection.stream.write_all(&connection.write_buffer);
+                    finish_consumer();
+                }
+            }
+        }
+    }
+    if !open || !flush(connection) {
+        return false;
+    }
+    // A peer that shut down its write side may still read the last answers,
+    // so it is kept until they are all out.
+    if connection.read_closed && connection.write_buffer.is_empty() {
+        return false;
+    }
+    let interest = interest(connection);
+    if interest != connection.interest {
+        let fd = connection.stream.as_raw_fd();
+        if register(epfd, EpollOp::EpollCtlMod, fd, token, interest).is_err() {
+            return false;
+        }
+        connection.interest = interest;
+    }
+    true
+}
+
+// Drains the socket into the read buffer, noting when the peer shut down
+// its side. Returns false on errors.
+fn fill(connection: &mut Connection) -> bool {
+    let mut chunk = [0; 4096];
+    loop {
+        match connection.stream.read(&mut chunk) {
+            Ok(0) => {
+                connection.read_closed = true;
+                // Like `BufRead::lines`, a last line without a newline
+                // still gets an answer.
+                if connection.read_buffer.last().is_some_and(|b| *b != b'\n') {
+                    connection.read_buffer.push(b'\n');
+                }
+                return true;
+            }
+            Ok(n) => connection.read_buffer.extend_from_slice(&chunk[..n]),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return true,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return false,
+        }
+    }
+}
+
+// Sends as much of the write buffer as possible. Returns false on errors.
+fn flush(connection: &mut Connection) -> bool {
+    while !connection.write_buffer.is_empty() {
+        match connection.stream.write(&connection.write_buffer) {
+            Ok(0) => return false,
+            Ok(n) => {
+                connection.write_buffer.drain(..n);
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return true,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::consumer::{Backend, Consumer};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{Shutdown, TcpStream};
+    use std::thread;
+
+    #[test]
+    fn answer_everything_after_the_peer_half_closes() {
+        let mut consumer = Consumer::from_socket("127.0.0.1:0".to_string())
+            .unwrap()
+            .with_backend(Backend::Epoll);
+        consumer.set_verbose(false);
+        let addr = consumer.local_addr().unwrap();
+        thread::spawn(move || consumer.read());
+
+        // Long even numbers are answered at once, and their answers fill the
+        // socket before the consumer sees the shutdown, so some are still
+        // queued when it does. The consumer stops reading while too many
+        // answers wait, so they are read as the requests go out.
+        let lines = 4_000;
+        let number = format!("1{}\n", "0".repeat(4_000));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let answers = BufReader::new(stream.try_clone().unwrap());
+        let reader = thread::spawn(move || answers.lines().count());
+        let request = number.repeat(lines);
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(lines, reader.join().unwrap());
+    }
+
+    #[test]
+    fn answer_a_last_line_without_newline() {
+        let mut consumer = Consumer::from_socket("127.0.0.1:0".to_string())
+            .unwrap()
+            .with_backend(Backend::Epoll);
+        consumer.set_verbose(false);
+        let addr = consumer.local_addr().unwrap();
+        thread::spawn(move || consumer.read());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"4\n7").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let answers = BufReader::new(stream)
+            .lines()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(["4 is not prime", "7 is prime"], answers[..]);
+    }
+}
@@ -1,2 +1,10 @@
 pub mod consumer;
+pub mod control;
+pub mod coordinator;
+mod event_loop;
+pub mod metrics;
+pub mod primality;
 pub mod producer;
+pub mod protocol;
+pub mod results;
+mod uring;
@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Error, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Upper bounds, in seconds, of the processing latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 1.0,
+];
+// Scrapes are answered one at a time, so a client that connects and goes
+// quiet only holds up the others for this long.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct ConnectionStats {
+    opened: Instant,
+    numbers: u64,
+}
+
+// Counters shared by every connection handled by a consumer.
+pub struct Metrics {
+    numbers: AtomicU64,
+    primes: AtomicU64,
+    connections_total: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_sum_ns: AtomicU64,
+    // Only open connections are kept here, so the label set stays small.
+    connections: Mutex<BTreeMap<String, ConnectionStats>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            numbers: AtomicU64::new(0),
+            primes: AtomicU64::new(0),
+            connections_total: AtomicU64::new(0),
+            latency_buckets: Default::default(),
+            latency_count: AtomicU64::new(0),
+            latency_sum_ns: AtomicU64::new(0),
+            connections: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn connection_opened(&self, peer: &str) {
+        self.connections_total.fetch_add(1, Ordering::Relaxed);
+        self.connections.lock().unwrap().insert(
+            peer.to_string(),
+            ConnectionStats {
+                opened: Instant::now(),
+                numbers: 0,
+            },
+        );
+    }
+
+    pub fn connection_closed(&self, peer: &str) {
+        self.connections.lock().unwrap().remove(peer);
+    }
+
+    pub fn observe(&self, peer: &str, is_prime: bool, elapsed: Duration) {
+        self.observe_batch(peer, 1, is_prime as u64);
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_ns
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    // Counts numbers checked together, like a range sent by the coordinator,
+    // which have no latency of their own.
+    pub fn observe_batch(&self, peer: &str, numbers: u64, primes: u64) {
+        self.numbers.fetch_add(numbers, Ordering::Relaxed);
+        self.primes.fetch_add(primes, Ordering::Relaxed);
+        if let Some(connection) = self.connections.lock().unwrap().get_mut(peer) {
+            connection.numbers += numbers;
+        }
+    }
+
+    pub fn numbers(&self) -> u64 {
+        self.numbers.load(Ordering::Relaxed)
+    }
+
+    pub fn primes(&self) -> u64 {
+        self.primes.load(Ordering::Relaxed)
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        let count = self.latency_count.load(Ordering::Relaxed);
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos(self.latency_sum_ns.load(Ordering::Relaxed) / count)
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    // Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP sd_numbers_processed_total Numbers checked for primality."
+        );
+        let _ = writeln!(out, "# TYPE sd_numbers_processed_total counter");
+        let _ = writeln!(out, "sd_numbers_processed_total {}", self.numbers());
+        let _ = writeln!(
+            out,
+            "# HELP sd_primes_found_total Numbers found to be prime."
+        );
+        let _ = writeln!(out, "# TYPE sd_primes_found_total counter");
+        let _ = writeln!(out, "sd_primes_found_total {}", self.primes());
+        let _ = writeln!(
+            out,
+            "# HELP sd_connections_total Producer connections accepted."
+        );
+        let _ = writeln!(out, "# TYPE sd_connections_total counter");
+        let _ = writeln!(
+            out,
+            "sd_connections_total {}",
+            self.connections_total.load(Ordering::Relaxed)
+        );
+
+        let connections = self.connections.lock().unwrap();
+        let _ = writeln!(
+            out,
+            "# HELP sd_active_connections Producer connections currently open."
+        );
+        let _ = writeln!(out, "# TYPE sd_active_connections gauge");
+        let _ = writeln!(out, "sd_active_connections {}", connections.len());
+        let _ = writeln!(
+            out,
+            "# HELP sd_connection_numbers_total Numbers received on an open connection."
+        );
+        let _ = writeln!(out, "# TYPE sd_connection_numbers_total counter");
+        for (peer, stats) in connections.iter() {
+            let _ = writeln!(
+                out,
+                "sd_connection_numbers_total{{peer=\"{}\"}} {}",
+                peer, stats.numbers
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP sd_connection_rate Numbers per second received on an open connection."
+        );
+        let _ = writeln!(out, "# TYPE sd_connection_rate gauge");
+        for (peer, stats) in connections.iter() {
+            let seconds = stats.opened.elapsed().as_secs_f64();
+            let rate = if seconds > 0.0 {
+                stats.numbers as f64 / seconds
+            } else {
+                0.0
+            };
+            let _ = writeln!(out, "sd_connection_rate{{peer=\"{}\"}} {:.3}", peer, rate);
+        }
+        drop(connections);
+
+        let _ = writeln!(
+            out,
+            "# HELP sd_processing_seconds Time spent checking a single number."
+        );
+        let _ = writeln!(out, "# TYPE sd_processing_seconds histogram");
+        let mut cumulative = 0;
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(self.latency_buckets.iter()) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "sd_processing_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        let count = self.latency_count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "sd_processing_seconds_bucket{{le=\"+Inf\"}} {}", count);
+        let _ = writeln!(
+            out,
+            "sd_processing_seconds_sum {}",
+            self.latency_sum_ns.load(Ordering::Relaxed) as f64 / 1e9
+        );
+        let _ = writeln!(out, "sd_processing_seconds_count {}", count);
+        out
+    }
+
+    // Serves `render` over plain HTTP at `addr` from a background thread.
+    pub fn serve(self: Arc<Self>, addr: &str) -> Result<JoinHandle<()>, Error> {
+        let listener = TcpListener::bind(addr)?;
+        println!("serving metrics on http://{}/metrics", addr);
+        Ok(self.serve_on(listener))
+    }
+
+    fn serve_on(self: Arc<Self>, listener: TcpListener) -> JoinHandle<()> {
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = self.answer_scrape(stream) {
+                    eprintln!("failed to answer metrics request: {}", e);
+                }
+            }
+        })
+    }
+
+    fn answer_scrape(&self, mut stream: TcpStream) -> Result<(), Error> {
+        stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+        stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Skip the headers, we do not need any of them.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+        let (status, body) = if path == "/metrics" || path == "/" {
+            ("200 OK", self.render())
+        } else {
+            ("404 Not Found", "not found\n".to_string())
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn render_counts_and_histogram() {
+        let metrics = Metrics::new();
+        metrics.connection_opened("127.0.0.1:4000");
+        metrics.observe("127.0.0.1:4000", true, Duration::from_nanos(500));
+        metrics.observe("127.0.0.1:4000", false, Duration::from_millis(2));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("sd_numbers_processed_total 2\n"));
+        assert!(rendered.contains("sd_primes_found_total 1\n"));
+        assert!(rendered.contains("sd_active_connections 1\n"));
+        assert!(rendered.contains("sd_connection_numbers_total{peer=\"127.0.0.1:4000\"} 2\n"));
+        assert!(rendered.contains("sd_processing_seconds_bucket{le=\"0.000001\"} 1\n"));
+        assert!(rendered.contains("sd_processing_seconds_bucket{le=\"0.005\"} 2\n"));
+        assert!(rendered.contains("sd_processing_seconds_count 2\n"));
+
+        metrics.connection_closed("127.0.0.1:4000");
+        assert_eq!(0, metrics.active_connections());
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn answer_scrapes_over_http() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.observe("127.0.0.1:4000", true, Duration::from_nanos(500));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        metrics.serve_on(listener);
+
+        // A client that never sends its request must not hold up the scrape.
+        let _idle = TcpStream::connect(addr).unwrap();
+        let response = get(addr, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        assert!(body.contains("sd_numbers_processed_total 1\n"));
+        assert!(body.contains("sd_primes_found_total 1\n"));
+
+        let response = get(addr, "/other");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with("\r\n\r\nnot found\n"));
+    }
+}
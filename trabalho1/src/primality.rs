@@ -0,0 +1,150 @@
+use num_bigint::BigUint;
+use num_traits::{One, ToPrimitive, Zero};
+use rand::Rng;
+
+// Checking these bases is enough for a deterministic Miller-Rabin test of
+// every number below 2^64.
+const U64_BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+// Rounds of the probabilistic test for numbers that do not fit in a u64.
+// Each round lets a composite pass with probability at most 1/4.
+pub const BIG_ROUNDS: usize = 32;
+
+// Tells whether `n` is prime. Numbers that fit in a u64 get an exact answer,
+// larger ones are checked with `BIG_ROUNDS` rounds of Miller-Rabin.
+pub fn is_prime(n: &BigUint) -> bool {
+    match n.to_u64() {
+        Some(n) => is_prime_u64(n),
+        None => is_probable_prime(n, BIG_ROUNDS),
+    }
+}
+
+pub fn is_prime_u64(n: u64) -> bool {
+    if n < 2 {
+        return false;
+    }
+    for p in U64_BASES {
+        if n.is_multiple_of(p) {
+            return n == p;
+        }
+    }
+    let (d, s) = split_even(n - 1);
+    U64_BASES.iter().all(|a| passes_u64(n, *a, d, s))
+}
+
+// Writes n - 1 as d * 2^s with d odd.
+fn split_even(n_minus_one: u64) -> (u64, u32) {
+    let s = n_minus_one.trailing_zeros();
+    (n_minus_one >> s, s)
+}
+
+fn passes_u64(n: u64, a: u64, d: u64, s: u32) -> bool {
+    let mut x = pow_mod(a, d, n);
+    if x == 1 || x == n - 1 {
+        return true;
+    }
+    for _ in 1..s {
+        x = mul_mod(x, x, n);
+        if x == n - 1 {
+            return true;
+        }
+    }
+    false
+}
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    ((a as u128 * b as u128) % m as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exp: u64, m: u64) -> u64 {
+    let mut result = 1;
+    base %= m;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    result
+}
+
+// Miller-Rabin with `rounds` random bases. A prime always passes, a composite
+// passes with probability at most 4^-rounds.
+pub fn is_probable_prime(n: &BigUint, rounds: usize) -> bool {
+    let one = BigUint::one();
+    let two = BigUint::from(2u32);
+    if n < &two {
+        return false;
+    }
+    for p in U64_BASES {
+        let p = BigUint::from(p);
+        if (n % &p).is_zero() {
+            return n == &p;
+        }
+    }
+
+    let n_minus_one = n - &one;
+    let s = n_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_one >> s;
+    let mut rng = rand::thread_rng();
+    // Bases are drawn from [2, n - 2].
+    let range = n - 3u32;
+    'rounds: for _ in 0..rounds {
+        let a = random_below(&mut rng, &range) + &two;
+        let mut x = a.modpow(&d, n);
+        if x == one || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = x.modpow(&two, n);
+            if x == n_minus_one {
+                continue 'rounds;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+fn random_below(rng: &mut impl Rng, bound: &BigUint) -> BigUint {
+    let mut bytes = vec![0u8; bound.to_bytes_le().len() + 8];
+    rng.fill(&mut bytes[..]);
+    BigUint::from_bytes_le(&bytes) % bound
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn small_numbers() {
+        let primes = [2, 3, 5, 7, 11, 13, 97, 7919, 2_147_483_647];
+        let composites = [0, 1, 4, 9, 15, 561, 1105, 7917, 2_147_483_649];
+        for p in primes {
+            assert!(is_prime_u64(p), "{} should be prime", p);
+        }
+        for c in composites {
+            assert!(!is_prime_u64(c), "{} should not be prime", c);
+        }
+    }
+
+    #[test]
+    fn beyond_32_bits() {
+        // Largest prime below 2^64 and a strong pseudoprime to several bases.
+        assert!(is_prime_u64(18_446_744_073_709_551_557));
+        assert!(!is_prime_u64(3_825_123_056_546_413_051));
+        assert!(!is_prime_u64(u64::MAX));
+    }
+
+    #[test]
+    fn big_numbers() {
+        let mersenne_127 = (BigUint::one() << 127u32) - 1u32;
+        assert!(is_prime(&mersenne_127));
+        assert!(!is_prime(&(mersenne_127.clone() * 3u32)));
+        let m89 = (BigUint::one() << 89u32) - 1u32;
+        assert!(!is_prime(&(&m89 * &mersenne_127)));
+        let carmichael = BigUint::from_str("3474749660383").unwrap();
+        assert!(!is_prime(&carmichael));
+    }
+}
@@ -1,19 +1,22 @@
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
 use rand::Rng;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, BufWriter, Error, Write};
 use std::net::TcpStream;
 use std::os::unix::io::{FromRawFd, RawFd};
 
+use crate::protocol::write_number;
+
 pub struct Producer {
     writer: Option<File>,
     stream: Option<TcpStream>,
 }
 
 impl Producer {
-    pub fn write(&mut self, int: i32) -> Result<(), Error> {
-        let int_bytes = i32::to_be_bytes(int);
+    pub fn write(&mut self, int: &BigUint) -> Result<(), Error> {
         if let Some(writer) = &mut self.writer {
-            return writer.write_all(&int_bytes);
+            return write_number(writer, int);
         }
         if let Some(stream) = &mut self.stream {
             let mut writer = BufWriter::new(stream.try_clone().unwrap());
@@ -48,15 +51,25 @@ impl Producer {
         })
     }
 
-    pub fn produce_random_ints(&mut self, int_numbers: i32) -> Result<(), Error> {
-        let mut int = 1;
+    pub fn produce_random_ints(&mut self, int_numbers: u64) -> Result<(), Error> {
+        self.produce_random_ints_from(BigUint::one(), int_numbers)
+    }
+
+    // Random walk starting at `start`, so the consumer can be fed numbers of
+    // any size.
+    pub fn produce_random_ints_from(
+        &mut self,
+        start: BigUint,
+        int_numbers: u64,
+    ) -> Result<(), Error> {
+        let mut int = start;
         let mut rng = rand::thread_rng();
-        self.write(int).unwrap(); //.expect("failed to send first integer");
+        self.write(&int).unwrap(); //.expect("failed to send first integer");
         for _ in 0..int_numbers {
-            int += rng.gen_range(0..101);
-            self.write(int).unwrap();
+            int += rng.gen_range(0..101u32);
+            self.write(&int).unwrap();
         }
-        self.write(0).expect("failed to send 0");
+        self.write(&BigUint::zero()).expect("failed to send 0");
         Ok(())
     }
 }
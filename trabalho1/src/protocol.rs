@@ -0,0 +1,68 @@
+use std::io::{Error, ErrorKind, Read, Write};
+
+use num_bigint::BigUint;
+
+// Numbers sent through a pipe are LEB128 encoded: 7 bits per byte, least
+// significant group first, with the high bit set on every byte but the last.
+// Small numbers take a single byte and there is no upper bound.
+pub fn write_number(writer: &mut impl Write, int: &BigUint) -> Result<(), Error> {
+    let mut bytes = int.to_radix_le(128);
+    let last = bytes.len() - 1;
+    for byte in &mut bytes[..last] {
+        *byte |= 0x80;
+    }
+    writer.write_all(&bytes)
+}
+
+// Reads a number written by `write_number`. Returns None if the stream ended
+// before a new number started.
+pub fn read_number(reader: &mut impl Read) -> Result<Option<BigUint>, Error> {
+    let mut digits = vec![];
+    let mut byte = [0; 1];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            if digits.is_empty() {
+                return Ok(None);
+            }
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "stream ended in the middle of a number",
+            ));
+        }
+        digits.push(byte[0] & 0x7f);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(BigUint::from_radix_le(&digits, 128))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_traits::One;
+
+    #[test]
+    fn round_trip() {
+        let numbers = vec![
+            BigUint::from(0u32),
+            BigUint::from(127u32),
+            BigUint::from(128u32),
+            BigUint::from(u64::MAX),
+            (BigUint::one() << 521u32) - 1u32,
+        ];
+        let mut wire = vec![];
+        for int in &numbers {
+            write_number(&mut wire, int).unwrap();
+        }
+        let mut single = vec![];
+        write_number(&mut single, &numbers[1]).unwrap();
+        assert_eq!(1, single.len());
+
+        let mut reader = &wire[..];
+        for int in &numbers {
+            assert_eq!(Some(int.clone()), read_number(&mut reader).unwrap());
+        }
+        assert_eq!(None, read_number(&mut reader).unwrap());
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Every binary log starts with these bytes so the reader can tell it
+// apart from the text formats. The last byte is the layout version.
+const BINARY_MAGIC: &[u8; 5] = b"SDRL\x02";
+const CSV_HEADER: &str = "timestamp_ms,producer,number,is_prime,elapsed_ns";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResultFormat {
+    Csv,
+    JsonLines,
+    Binary,
+}
+
+impl ResultFormat {
+    // Guess the format from the file extension, falling back to CSV.
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") | Some("json") => Self::JsonLines,
+            Some("bin") | Some("log") => Self::Binary,
+            _ => Self::Csv,
+        }
+    }
+}
+
+impl FromStr for ResultFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "jsonl" | "json" => Ok(Self::JsonLines),
+            "bin" | "binary" => Ok(Self::Binary),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown result format '{}', use csv, jsonl or bin", s),
+            )),
+        }
+    }
+}
+
+// A single answer given by the consumer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub timestamp_ms: u64,
+    pub producer: String,
+    pub number: String,
+    pub is_prime: bool,
+    pub elapsed: Duration,
+}
+
+impl Verdict {
+    pub fn new(producer: &str, number: impl ToString, is_prime: bool, elapsed: Duration) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self {
+            timestamp_ms,
+            producer: producer.to_string(),
+            number: number.to_string(),
+            is_prime,
+            elapsed,
+        }
+    }
+}
+
+// Append-only destination for verdicts. Every record is flushed right away
+// since the consumer may leave through `std::process::exit`, which does not
+// run destructors.
+pub struct ResultSink {
+    format: ResultFormat,
+    writer: BufWriter<File>,
+}
+
+impl ResultSink {
+    pub fn create(path: &str, format: ResultFormat) -> Result<Self, Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let is_empty = file.metadata()?.len() == 0;
+        // Appending in another format would leave a file no reader understands.
+        if !is_empty && existing_format(&mut file)? != Some(format) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("'{}' is not a {:?} results file", path, format),
+            ));
+        }
+        let mut writer = BufWriter::new(file);
+        if is_empty {
+            match format {
+                ResultFormat::Csv => writeln!(writer, "{}", CSV_HEADER)?,
+                ResultFormat::Binary => writer.write_all(BINARY_MAGIC)?,
+                ResultFormat::JsonLines => {}
+            }
+            writer.flush()?;
+        }
+        Ok(Self { format, writer })
+    }
+
+    pub fn format(&self) -> ResultFormat {
+        self.format
+    }
+
+    pub fn record(&mut self, verdict: &Verdict) -> Result<(), Error> {
+        match self.format {
+            ResultFormat::Csv => writeln!(
+                self.writer,
+                "{},{},{},{},{}",
+                verdict.timestamp_ms,
+                verdict.producer.replace(',', ";"),
+                verdict.number,
+                verdict.is_prime,
+                verdict.elapsed.as_nanos()
+            )?,
+            ResultFormat::JsonLines => writeln!(
+                self.writer,
+                "{{\"timestamp_ms\":{},\"producer\":\"{}\",\"number\":\"{}\",\"is_prime\":{},\"elapsed_ns\":{}}}",
+                verdict.timestamp_ms,
+                escape_json(&verdict.producer),
+                verdict.number,
+                verdict.is_prime,
+                verdict.elapsed.as_nanos()
+            )?,
+            ResultFormat::Binary => {
+                // timestamp (u64) | elapsed ns (u64) | is_prime (u8)
+                // | producer length (u32) + bytes | number length (u32) + decimal bytes
+                self.writer.write_all(&verdict.timestamp_ms.to_be_bytes())?;
+                self.writer
+                    .write_all(&(verdict.elapsed.as_nanos() as u64).to_be_bytes())?;
+                self.writer.write_all(&[verdict.is_prime as u8])?;
+                write_field(&mut self.writer, verdict.producer.as_bytes())?;
+                write_field(&mut self.writer, verdict.number.as_bytes())?;
+            }
+        }
+        self.writer.flush()
+    }
+}
+
+fn write_field(writer: &mut impl Write, bytes: &[u8]) -> Result<(), Error> {
+    let len = u32::try_from(bytes.len())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "field too long for binary log"))?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_field(reader: &mut impl Read) -> Result<String, Error> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+// Tells which format a non-empty results file was written in, from its
+// first bytes.
+fn existing_format(file: &mut File) -> Result<Option<ResultFormat>, Error> {
+    let mut head = vec![];
+    file.take(CSV_HEADER.len() as u64).read_to_end(&mut head)?;
+    Ok(if head.starts_with(BINARY_MAGIC) {
+        Some(ResultFormat::Binary)
+    } else if head == CSV_HEADER.as_bytes() {
+        Some(ResultFormat::Csv)
+    } else if head.starts_with(b"{") {
+        Some(ResultFormat::JsonLines)
+    } else {
+        None
+    })
+}
+
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Reads every verdict from a results file, detecting its format from the
+// content instead of trusting the extension.
+pub fn read_verdicts(path: &str) -> Result<Vec<Verdict>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let head = reader.fill_buf()?;
+    if head.starts_with(BINARY_MAGIC) {
+        reader.consume(BINARY_MAGIC.len());
+        return read_binary(reader);
+    }
+    let mut verdicts = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line == CSV_HEADER {
+            continue;
+        }
+        let verdict = if line.starts_with('{') {
+            parse_json_line(line)
+        } else {
+            parse_csv_line(line)
+        };
+        verdicts.push(verdict.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("malformed record: {}", line),
+            )
+        })?);
+    }
+    Ok(verdicts)
+}
+
+fn read_binary(mut reader: impl Read) -> Result<Vec<Verdict>, Error> {
+    let mut verdicts = vec![];
+    loop {
+        let mut timestamp = [0; 8];
+        match reader.read_exact(&mut timestamp) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let mut elapsed = [0; 8];
+        reader.read_exact(&mut elapsed)?;
+        let mut is_prime = [0; 1];
+        reader.read_exact(&mut is_prime)?;
+        verdicts.push(Verdict {
+            timestamp_ms: u64::from_be_bytes(timestamp),
+            elapsed: Duration::from_nanos(u64::from_be_bytes(elapsed)),
+            is_prime: is_prime[0] != 0,
+            producer: read_field(&mut reader)?,
+            number: read_field(&mut reader)?,
+        });
+    }
+    Ok(verdicts)
+}
+
+fn parse_csv_line(line: &str) -> Option<Verdict> {
+    let fields = line.split(',').collect::<Vec<_>>();
+    if fields.len() != 5 {
+        return None;
+    }
+    Some(Verdict {
+        timestamp_ms: fields[0].parse().ok()?,
+        producer: fields[1].to_string(),
+        number: fields[2].to_string(),
+        is_prime: fields[3].parse().ok()?,
+        elapsed: Duration::from_nanos(fields[4].parse().ok()?),
+    })
+}
+
+// The JSON lines are written by `ResultSink` with a fixed, flat layout, so a
+// field lookup is enough to read them back.
+fn parse_json_line(line: &str) -> Option<Verdict> {
+    Some(Verdict {
+        timestamp_ms: json_field(line, "timestamp_ms")?.parse().ok()?,
+        producer: json_field(line, "producer")?,
+        number: json_field(line, "number")?,
+        is_prime: json_field(line, "is_prime")?.parse().ok()?,
+        elapsed: Duration::from_nanos(json_field(line, "elapsed_ns")?.parse().ok()?),
+    })
+}
+
+fn json_field(line: &str, key: &str) -> Option<String> {
+    let pattern = format!("\"{}\":", key);
+    let rest = &line[line.find(&pattern)? + pattern.len()..];
+    if let Some(quoted) = rest.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next()? {
+                    'n' => value.push('\n'),
+                    'u' => {
+                        let code = chars.by_ref().take(4).collect::<String>();
+                        value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                    }
+                    c => value.push(c),
+                },
+                '"' => return Some(value),
+                _ => value.push(c),
+            }
+        }
+        None
+    } else {
+        let end = rest.find([',', '}'])?;
+        Some(rest[..end].trim().to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProducerSummary {
+    pub numbers: usize,
+    pub primes: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct ResultSummary {
+    pub numbers: usize,
+    pub primes: usize,
+    pub total_elapsed: Duration,
+    pub max_elapsed: Duration,
+    pub first_timestamp_ms: Option<u64>,
+    pub last_timestamp_ms: Option<u64>,
+    pub producers: BTreeMap<String, ProducerSummary>,
+}
+
+impl ResultSummary {
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        Ok(Self::from_verdicts(&read_verdicts(path)?))
+    }
+
+    pub fn from_verdicts(verdicts: &[Verdict]) -> Self {
+        let mut summary = Self::default();
+        for verdict in verdicts {
+            summary.numbers += 1;
+            summary.total_elapsed += verdict.elapsed;
+            summary.max_elapsed = summary.max_elapsed.max(verdict.elapsed);
+            let producer = summary
+                .producers
+                .entry(verdict.producer.clone())
+                .or_default();
+            producer.numbers += 1;
+            if verdict.is_prime {
+                summary.primes += 1;
+                producer.primes += 1;
+            }
+            summary.first_timestamp_ms = Some(
+                summary
+                    .first_timestamp_ms
+                    .map_or(verdict.timestamp_ms, |t| t.min(verdict.timestamp_ms)),
+            );
+            summary.last_timestamp_ms = Some(
+                summary
+                    .last_timestamp_ms
+                    .map_or(verdict.timestamp_ms, |t| t.max(verdict.timestamp_ms)),
+            );
+        }
+        summary
+    }
+
+    pub fn mean_elapsed(&self) -> Duration {
+        if self.numbers == 0 {
+            return Duration::ZERO;
+        }
+        self.total_elapsed / self.numbers as u32
+    }
+}
+
+impl fmt::Display for ResultSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "numbers processed: {}", self.numbers)?;
+        writeln!(f, "primes found:      {}", self.primes)?;
+        writeln!(f, "mean computation:  {:?}", self.mean_elapsed())?;
+        writeln!(f, "max computation:   {:?}", self.max_elapsed)?;
+        if let (Some(first), Some(last)) = (self.first_timestamp_ms, self.last_timestamp_ms) {
+            writeln!(
+                f,
+                "time span:         {:?}",
+                Duration::from_millis(last - first)
+            )?;
+        }
+        writeln!(f, "producers:")?;
+        for (producer, summary) in &self.producers {
+            writeln!(
+                f,
+                "  {}: {} numbers, {} primes",
+                producer, summary.numbers, summary.primes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<Verdict> {
+        vec![
+            Verdict::new("127.0.0.1:4000", 7, true, Duration::from_nanos(120)),
+            Verdict::new("127.0.0.1:4000", 8, false, Duration::from_nanos(80)),
+            Verdict::new("fd:3", 11, true, Duration::from_nanos(100)),
+        ]
+    }
+
+    #[test]
+    fn round_trip_every_format() {
+        for format in [
+            ResultFormat::Csv,
+            ResultFormat::JsonLines,
+            ResultFormat::Binary,
+        ] {
+            let path = std::env::temp_dir().join(format!(
+                "sd-results-{}-{:?}",
+                std::process::id(),
+                format
+            ));
+            let path = path.to_str().unwrap();
+            let _ = std::fs::remove_file(path);
+
+            let mut sink = ResultSink::create(path, format).unwrap();
+            for verdict in sample() {
+                sink.record(&verdict).unwrap();
+            }
+            drop(sink);
+
+            let got = read_verdicts(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            let expected = sample();
+            assert_eq!(expected.len(), got.len());
+            for (e, g) in expected.iter().zip(got.iter()) {
+                assert_eq!(e.producer, g.producer);
+                assert_eq!(e.number, g.number);
+                assert_eq!(e.is_prime, g.is_prime);
+                assert_eq!(e.elapsed, g.elapsed);
+            }
+        }
+    }
+
+    #[test]
+    fn binary_log_keeps_long_numbers() {
+        let path = std::env::temp_dir().join(format!("sd-results-{}-long", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let mut verdict = sample().remove(0);
+        verdict.number = "7".repeat(100_000);
+        let mut sink = ResultSink::create(path, ResultFormat::Binary).unwrap();
+        sink.record(&verdict).unwrap();
+        drop(sink);
+
+        let got = read_verdicts(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(1, got.len());
+        assert_eq!(verdict.number, got[0].number);
+    }
+
+    #[test]
+    fn json_escapes_control_characters() {
+        let path = std::env::temp_dir().join(format!("sd-results-{}-escape", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let producer = "a \"b\"\n\\c\t\u{1}";
+        let mut sink = ResultSink::create(path, ResultFormat::JsonLines).unwrap();
+        sink.record(&Verdict::new(producer, 7, true, Duration::from_nanos(1)))
+            .unwrap();
+        drop(sink);
+
+        let content = std::fs::read_to_string(path).unwrap();
+        let got = read_verdicts(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(1, content.lines().count());
+        assert_eq!(producer, got[0].producer);
+    }
+
+    #[test]
+    fn refuse_to_append_in_another_format() {
+        let path = std::env::temp_dir().join(format!("sd-results-{}-mixed", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let mut sink = ResultSink::create(path, ResultFormat::Csv).unwrap();
+        sink.record(&sample()[0]).unwrap();
+        drop(sink);
+
+        let mixed = [ResultFormat::JsonLines, ResultFormat::Binary];
+        let refused = mixed
+            .iter()
+            .all(|&format| ResultSink::create(path, format).is_err());
+        let reopened = ResultSink::create(path, ResultFormat::Csv).is_ok();
+        std::fs::remove_file(path).unwrap();
+        assert!(refused);
+        assert!(reopened);
+    }
+
+    #[test]
+    fn summarize_verdicts() {
+        let summary = ResultSummary::from_verdicts(&sample());
+        assert_eq!(3, summary.numbers);
+        assert_eq!(2, summary.primes);
+        assert_eq!(Duration::from_nanos(100), summary.mean_elapsed());
+        assert_eq!(2, summary.producers["127.0.0.1:4000"].numbers);
+        assert_eq!(1, summary.producers["fd:3"].primes);
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::ptr;
+use std::time::Duration;
+
+use io_uring::{opcode, squeue, types, IoUring};
+
+use crate::consumer::{finish_consumer, Context, Reply};
+
+const RING_ENTRIES: u32 = 256;
+// How many accepts are kept queued in the ring at any time.
+const ACCEPT_BATCH: usize = 16;
+const READ_BUFFER_SIZE: usize = 4096;
+// How long a failed accept waits before it is queued again, so that running
+// out of file descriptors does not spin the loop.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// The low bits of a request's user data say which operation it was, the
+// remaining bits identify the connection.
+const OP_ACCEPT: u64 = 0;
+const OP_READ: u64 = 1;
+const OP_WRITE: u64 = 2;
+const OP_RETRY_ACCEPT: u64 = 3;
+const OP_BITS: u64 = 2;
+
+// A producer connection. The kernel owns `read_buffer` while `reading` is set
+// and `in_flight` while `writing` is set, so neither may be touched until the
+// matching completion arrives.
+struct Connection {
+    stream: TcpStream,
+    producer: String,
+    read_buffer: Box<[u8]>,
+    // Bytes of a line that did not arrive completely yet.
+    pending: Vec<u8>,
+    in_flight: Vec<u8>,
+    // Answers produced while a write was already in flight.
+    queued: Vec<u8>,
+    reading: bool,
+    writing: bool,
+    closing: bool,
+    // The producer sent 0: the consumer stops once the answers are out.
+    finishing: bool,
+}
+
+// Serves every producer from the calling thread. Accepts, reads and writes
+// are queued in an io_uring and submitted together on each turn of the loop.
+pub(crate) fn run(listener: &TcpListener, context: &Context) -> Result<(), Error> {
+    // The buffers and the timespec the kernel points at are declared before
+    // the ring, so they outlive it when an error returns early.
+    let mut connections: HashMap<u64, Connection> = HashMap::new();
+    let mut next_token = 0;
+    let backoff = types::Timespec::from(ACCEPT_BACKOFF);
+    let mut ring = IoUring::new(RING_ENTRIES)?;
+    let listener_fd = types::Fd(listener.as_raw_fd());
+    let accept = opcode::Accept::new(listener_fd, ptr::null_mut(), ptr::null_mut())
+        .build()
+        .user_data(OP_ACCEPT);
+    let retry_accept = opcode::Timeout::new(&backoff)
+        .build()
+        .user_data(OP_RETRY_ACCEPT);
+    for _ in 0..ACCEPT_BATCH {
+        push(&mut ring, &accept)?;
+    }
+
+    loop {
+        match ring.submit_and_wait(1) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        let completions = ring
+            .completion()
+            .map(|entry| (entry.user_data(), entry.result()))
+            .collect::<Vec<_>>();
+        for (user_data, result) in completions {
+            let token = user_data >> OP_BITS;
+            let op = user_data & ((1 << OP_BITS) - 1);
+            match op {
+                OP_ACCEPT => {
+                    if result < 0 {
+                        eprintln!("failed to accept: {}", Error::from_raw_os_error(-result));
+                        push(&mut ring, &retry_accept)?;
+                        continue;
+                    }
+                    push(&mut ring, &accept)?;
+                    let stream = unsafe { TcpStream::from_raw_fd(result) };
+                    // Dropping the stream turns the producer away.
+                    if context.is_draining() {
+                        continue;
+                    }
+                    let producer = stream
+                        .peer_addr()
+                        .map(|addr| addr.to_string())
+                        .unwrap_or_else(|_| "unknown".to_string());
+                    context.metrics.connection_opened(&producer);
+                    let token = next_token;
+                    next_token += 1;
+                    let mut connection = Connection {
+                        stream,
+                        producer,
+                        read_buffer: vec![0; READ_BUFFER_SIZE].into_boxed_slice(),
+                        pending: Vec::new(),
+                        in_flight: Vec::new(),
+                        queued: Vec::new(),
+                        reading: false,
+                        writing: false,
+                        closing: false,
+                        finishing: false,
+                    };
+                    submit_read(&mut ring, token, &mut connection)?;
+                    connections.insert(token, connection);
+                }
+                OP_READ => {
+                    let connection = match connections.get_mut(&token) {
+                        Some(connection) => connection,
+                        None => continue,
+                    };
+                    connection.reading = false;
+                    if result <= 0 {
+                        connection.closing = true;
+                        // Like `BufRead::lines`, a last line without a
+                        // newline still gets an answer.
+                        if result == 0 && connection.pending.last().is_some_and(|b| *b != b'\n') {
+                            connection.pending.push(b'\n');
+                        }
+                    } else {
+                        let read = &connection.read_buffer[..result as usize];
+                        connection.pending.extend_from_slice(read);
+                    }
+                    answer_lines(connection, context);
+                    if !connection.writing && !connection.queued.is_empty() {
+                        connection.in_flight = std::mem::take(&mut connection.queued);
+                        submit_write(&mut ring, token, connection)?;
+                    }
+                    if !connection.closing && !connection.finishing {
+                        submit_read(&mut ring, token, connection)?;
+                    }
+                }
+                OP_WRITE => {
+                    let connection = match connections.get_mut(&token) {
+                        Some(connection) => connection,
+                        None => continue,
+                    };
+                    connection.writing = false;
+                    if result < 0 {
+                        if connection.finishing {
+                            finish_consumer();
+                        }
+                        connection.closing = true;
+                        // Wake up the pending read so the connection can go away.
+                        let _ = connection.stream.shutdown(Shutdown::Both);
+                    } else {
+                        connection.in_flight.drain(..result as usize);
+                        if connection.in_flight.is_empty() {
+                            connection.in_flight = std::mem::take(&mut connection.queued);
+                        }
+                        if !connection.in_flight.is_empty() {
+                            submit_write(&mut ring, token, connection)?;
+                        } else if connection.finishing {
+                            finish_consumer();
+                        }
+                    }
+                }
+                OP_RETRY_ACCEPT => push(&mut ring, &accept)?,
+                _ => unreachable!("unknown io_uring operation"),
+            }
+            if op == OP_ACCEPT || op == OP_RETRY_ACCEPT {
+                continue;
+            }
+            let done = connections
+                .get(&token)
+                .is_some_and(|c| c.closing && !c.reading && !c.writing);
+            if done {
+                let connection = connections.remove(&token).unwrap();
+                context.metrics.connection_closed(&connection.producer);
+            }
+        }
+    }
+}
+
+// Answers every complete line waiting in the connection's pending bytes, up
+// to a 0 from the producer.
+fn answer_lines(connection: &mut Connection, context: &Context) {
+    while let Some(end) = connection.pending.iter().position(|b| *b == b'\n') {
+        let line = connection.pending.drain(..=end).collect::<Vec<_>>();
+        let line = String::from_utf8_lossy(&line);
+        match context.reply(&connection.producer, &line) {
+            Reply::Answer(answer) => connection.queued.extend(answer.as_bytes()),
+            Reply::Finish(message) => {
+                connection.queued.extend(message.as_bytes());
+                // The kernel still owns the write in flight, so the message
+                // goes out after it, once its completion arrives.
+                if connection.writing {
+                    connection.finishing = true;
+                    return;
+                }
+                let _ = connection.stream.write_all(&connection.queued);
+                finish_consumer();
+            }
+        }
+    }
+}
+
+fn submit_read(ring: &mut IoUring, token: u64, connection: &mut Connection) -> Result<(), Error> {
+    let read = opcode::Read::new(
+        types::Fd(connection.stream.as_raw_fd()),
+        connection.read_buffer.as_mut_ptr(),
+        connection.read_buffer.len() as u32,
+    )
+    .build()
+    .user_data(token << OP_BITS | OP_READ);
+    push(ring, &read)?;
+    connection.reading = true;
+    Ok(())
+}
+
+fn submit_write(ring: &mut IoUring, token: u64, connection: &mut Connection) -> Result<(), Error> {
+    let write = opcode::Write::new(
+        types::Fd(connection.stream.as_raw_fd()),
+        connection.in_flight.as_ptr(),
+        connection.in_flight.len() as u32,
+    )
+    .build()
+    .user_data(token << OP_BITS | OP_WRITE);
+    push(ring, &write)?;
+    connection.writing = true;
+    Ok(())
+}
+
+// Queues an entry, flushing the submission queue to the kernel if it is full.
+fn push(ring: &mut IoUring, entry: &squeue::Entry) -> Result<(), Error> {
+    loop {
+        // The buffers referenced by the entries live in their connection
+        // until the matching completion is reaped.
+        if unsafe { ring.submission().push(entry) }.is_ok() {
+            return Ok(());
+        }
+        ring.submit()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::consumer::{Backend, Consumer};
+    use io_uring::IoUring;
+    use nix::libc;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{Shutdown, SocketAddr, TcpStream};
+    use std::thread;
+
+    // Starts an io_uring consumer, or returns None where the kernel or a
+    // seccomp filter does not allow io_uring.
+    fn start_consumer() -> Option<SocketAddr> {
+        if let Err(e) = IoUring::new(8) {
+            match e.raw_os_error() {
+                Some(libc::ENOSYS) | Some(libc::EPERM) => return None,
+                _ => panic!("failed to set up io_uring: {e}"),
+            }
+        }
+        let mut consumer = Consumer::from_socket("127.0.0.1:0".to_string())
+            .unwrap()
+            .with_backend(Backend::IoUring);
+        consumer.set_verbose(false);
+        let addr = consumer.local_addr().unwrap();
+        thread::spawn(move || consumer.read());
+        Some(addr)
+    }
+
+    #[test]
+    fn answer_everything_after_the_peer_half_closes() {
+        let Some(addr) = start_consumer() else {
+            return;
+        };
+        let lines = 4_000;
+        let number = format!("1{}\n", "0".repeat(4_000));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let answers = BufReader::new(stream.try_clone().unwrap());
+        let reader = thread::spawn(move || answers.lines().count());
+        let request = number.repeat(lines);
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(lines, reader.join().unwrap());
+    }
+
+    #[test]
+    fn answer_a_last_line_without_newline() {
+        let Some(addr) = start_consumer() else {
+            return;
+        };
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"4\n7").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let answers = BufReader::new(stream)
+            .lines()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(["4 is not prime", "7 is prime"], answers[..]);
+    }
+}
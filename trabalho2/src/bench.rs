@@ -0,0 +1,774 @@
+use crate::contention::ContentionStats;
+use log::info;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Settings for a benchmark run. Read from a file given with `--config`,
+// holding one `key = value` pair per line (`#` starts a comment), and then
+// from `--key value` or `--key=value` arguments, which take precedence.
+// The switches a program declares are set to true by `--key` alone and
+// never take the next argument as their value; any other `--key` with no
+// value after it is a switch as well. Anything else is kept as a
+// positional argument.
+//
+// Keys every benchmark understands:
+//   warmup       runs thrown away before measuring each case (default 1)
+//   repetitions  measured runs per case (default 10)
+//   output       where the per-case summary goes, `-` for stdout
+//   format       csv or json (default csv)
+//   samples      optional CSV file with every measured run
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BenchConfig {
+    values: BTreeMap<String, String>,
+    positional: Vec<String>,
+}
+
+const COMMON_KEYS: [&str; 6] = [
+    "config",
+    "warmup",
+    "repetitions",
+    "output",
+    "format",
+    "samples",
+];
+
+impl BenchConfig {
+    pub fn from_args(
+        args: impl IntoIterator<Item = String>,
+        switches: &[&str],
+    ) -> Result<Self, String> {
+        let mut config = Self::default();
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            let key = match arg.strip_prefix("--") {
+                Some(key) => key,
+                None => {
+                    config.positional.push(arg);
+                    continue;
+                }
+            };
+            let (key, value) = match key.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None if switches.contains(&key) => (key.to_string(), "true".to_string()),
+                None => match args.next_if(|next| !next.starts_with("--")) {
+                    Some(value) => (key.to_string(), value),
+                    None => (key.to_string(), "true".to_string()),
+                },
+            };
+            config.values.insert(key, value);
+        }
+
+        // Values from the file only fill in what the command line left out.
+        if let Some(path) = config.values.get("config").cloned() {
+            let contents =
+                fs::read_to_string(&path).map_err(|e| format!("reading {}: {}", path, e))?;
+            for (key, value) in Self::from_str(&contents)?.values {
+                config.values.entry(key).or_insert(value);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    pub fn set_default(&mut self, key: &str, value: &str) {
+        self.values
+            .entry(key.to_string())
+            .or_insert_with(|| value.to_string());
+    }
+
+    pub fn flag(&self, key: &str) -> bool {
+        self.get(key) == Some("true")
+    }
+
+    pub fn value<T: FromStr>(&self, key: &str, default: T) -> Result<T, String>
+    where
+        T::Err: Display,
+    {
+        match self.get(key) {
+            Some(value) => parse(key, value),
+            None => Ok(default),
+        }
+    }
+
+    // Comma separated values, e.g. `--k 1,2,4`.
+    pub fn list<T: FromStr + Clone>(&self, key: &str, default: &[T]) -> Result<Vec<T>, String>
+    where
+        T::Err: Display,
+    {
+        match self.get(key) {
+            Some(values) => values
+                .split(',')
+                .map(|value| parse(key, value.trim()))
+                .collect(),
+            None => Ok(default.to_vec()),
+        }
+    }
+
+    // Like `value`, for counts that make no sense as 0.
+    pub fn count(&self, key: &str, default: usize) -> Result<usize, String> {
+        Ok(self.counts(key, &[default])?[0])
+    }
+
+    // Like `list`, for counts that make no sense as 0.
+    pub fn counts(&self, key: &str, default: &[usize]) -> Result<Vec<usize>, String> {
+        let counts = self.list(key, default)?;
+        if counts.contains(&0) {
+            return Err(format!("{}: must be at least 1", key));
+        }
+        Ok(counts)
+    }
+
+    // Catches typos: fails on keys that are neither common nor in `known`.
+    pub fn check_keys(&self, known: &[&str]) -> Result<(), String> {
+        match self
+            .values
+            .keys()
+            .find(|key| !COMMON_KEYS.contains(&key.as_str()) && !known.contains(&key.as_str()))
+        {
+            Some(key) => Err(format!("unknown setting '{}'", key)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("{}: invalid value '{}': {}", key, value, e))
+}
+
+impl FromStr for BenchConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("expected key = value: {}", line))?;
+            config
+                .values
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            s => Err(format!("unknown output format '{}'", s)),
+        }
+    }
+}
+
+// What identifies a case, e.g. k = 4, n = 1000, lock = mcs. The labels
+// become the first columns of every output row, in the order given.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Case {
+    labels: Vec<(String, String)>,
+}
+
+impl Case {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, key: &str, value: impl ToString) -> Self {
+        self.labels.push((key.to_string(), value.to_string()));
+        self
+    }
+}
+
+// A single measured run. `extra` holds anything else worth keeping in the
+// samples file, such as the sum that was computed, and `contention` the
+// counters of the lock or semaphore that was measured, if it keeps them.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Sample {
+    pub time: Duration,
+    pub extra: Vec<(String, String)>,
+    pub contention: Option<ContentionStats>,
+}
+
+impl Sample {
+    pub fn with(mut self, key: &str, value: impl ToString) -> Self {
+        self.extra.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_contention(mut self, stats: ContentionStats) -> Self {
+        self.contention = Some(stats);
+        self
+    }
+}
+
+impl From<Duration> for Sample {
+    fn from(time: Duration) -> Self {
+        Sample {
+            time,
+            ..Sample::default()
+        }
+    }
+}
+
+const STATS: bool = cfg!(feature = "stats");
+
+// Written after the other columns, both in the samples file and in the
+// summary, when built with the `stats` feature. In the summary they are
+// averages over the repetitions, except for the longest queue seen.
+pub const CONTENTION_COLUMNS: [&str; 4] = ["acquisitions", "spins", "wait_ms", "max_queue"];
+
+fn contention_fields(stats: Option<ContentionStats>) -> Vec<String> {
+    match stats {
+        Some(stats) => vec![
+            stats.acquisitions.to_string(),
+            stats.spins.to_string(),
+            format!("{:.3}", stats.waited.as_secs_f64() * 1000.0),
+            stats.max_queue.to_string(),
+        ],
+        None => vec![String::new(); CONTENTION_COLUMNS.len()],
+    }
+}
+
+// The contention of a case as the last field of its JSON object.
+fn contention_json(stats: Option<ContentionStats>) -> String {
+    if !STATS {
+        return String::new();
+    }
+    if stats.is_none() {
+        return ", \"contention\": null".to_string();
+    }
+    let values = CONTENTION_COLUMNS
+        .iter()
+        .zip(contention_fields(stats))
+        .map(|(key, value)| format!("{}: {}", json_string(key), value))
+        .collect::<Vec<_>>();
+    format!(", \"contention\": {{{}}}", values.join(", "))
+}
+
+fn mean_contention(samples: &[ContentionStats]) -> Option<ContentionStats> {
+    let n = samples.len() as u64;
+    let total = samples.iter().copied().reduce(ContentionStats::merge)?;
+    Some(ContentionStats {
+        acquisitions: total.acquisitions / n,
+        spins: total.spins / n,
+        waited: total.waited / n as u32,
+        max_queue: total.max_queue,
+    })
+}
+
+// Summary of the run times of a case, in milliseconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub samples: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+    pub p90: f64,
+    pub p99: f64,
+    // 95% confidence interval for the mean, using Student's t.
+    pub ci95: (f64, f64),
+}
+
+// Two-sided 95% critical values of Student's t for 1 to 30 degrees of
+// freedom. Beyond that the normal distribution is close enough.
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+impl Stats {
+    pub fn from_samples(samples: &[f64]) -> Option<Stats> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let n = sorted.len();
+        let mean = sorted.iter().sum::<f64>() / n as f64;
+        // Sample standard deviation, zero for a single run.
+        let stddev = if n > 1 {
+            let square_sum = sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>();
+            (square_sum / (n - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+        let t = match n - 1 {
+            0 => 0.0,
+            df if df <= T_95.len() => T_95[df - 1],
+            _ => 1.96,
+        };
+        let margin = t * stddev / (n as f64).sqrt();
+        Some(Stats {
+            samples: n,
+            mean,
+            median: percentile(&sorted, 50.0),
+            stddev,
+            min: sorted[0],
+            max: sorted[n - 1],
+            p90: percentile(&sorted, 90.0),
+            p99: percentile(&sorted, 99.0),
+            ci95: (mean - margin, mean + margin),
+        })
+    }
+}
+
+// Linear interpolation between the two closest ranks of sorted values.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let below = rank.floor() as usize;
+    let above = rank.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+// Where the numbers were taken, written along with the results.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub benchmark: String,
+    pub version: String,
+    pub host: String,
+    pub os: String,
+    pub arch: String,
+    pub cpu: String,
+    pub cpus: usize,
+    // Seconds since the Unix epoch when the run started.
+    pub started: u64,
+}
+
+impl Metadata {
+    pub fn collect(benchmark: &str) -> Self {
+        let cpu = fs::read_to_string("/proc/cpuinfo")
+            .ok()
+            .and_then(|info| {
+                info.lines()
+                    .find(|line| line.starts_with("model name"))
+                    .and_then(|line| line.split_once(':'))
+                    .map(|(_, model)| model.trim().to_string())
+            })
+            .unwrap_or_else(|| "unknown".to_string());
+        let host = fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|host| host.trim().to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
+        Metadata {
+            benchmark: benchmark.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            host,
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            cpu,
+            cpus: thread::available_parallelism().map_or(1, |n| n.get()),
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("benchmark", self.benchmark.clone()),
+            ("version", self.version.clone()),
+            ("host", self.host.clone()),
+            ("os", self.os.clone()),
+            ("arch", self.arch.clone()),
+            ("cpu", self.cpu.clone()),
+            ("cpus", self.cpus.to_string()),
+            ("started", self.started.to_string()),
+        ]
+    }
+}
+
+#[derive(Debug)]
+struct Record {
+    case: Case,
+    stats: Stats,
+    samples: Vec<f64>,
+    contention: Vec<ContentionStats>,
+}
+
+// Runs every case `warmup` times without measuring it and then
+// `repetitions` times, and writes a summary of each case when finished.
+// The output files are created up front, so a bad path is reported before
+// the benchmark runs rather than after.
+pub struct Bench {
+    warmup: usize,
+    repetitions: usize,
+    format: OutputFormat,
+    // None writes the summary to stdout.
+    output: Option<BufWriter<File>>,
+    samples: Option<BufWriter<File>>,
+    samples_header: bool,
+    metadata: Metadata,
+    records: Vec<Record>,
+}
+
+impl Bench {
+    pub fn new(benchmark: &str, config: &BenchConfig) -> Result<Self, String> {
+        let create = |path: &str| {
+            File::create(path)
+                .map(BufWriter::new)
+                .map_err(|e| format!("creating {}: {}", path, e))
+        };
+        let output = match config.get("output").unwrap_or("-") {
+            "-" => None,
+            path => Some(create(path)?),
+        };
+        let samples = config.get("samples").map(create).transpose()?;
+        Ok(Bench {
+            warmup: config.value("warmup", 1)?,
+            repetitions: config.count("repetitions", 10)?,
+            format: config.value("format", OutputFormat::Csv)?,
+            output,
+            samples,
+            samples_header: false,
+            metadata: Metadata::collect(benchmark),
+            records: vec![],
+        })
+    }
+
+    pub fn run<S: Into<Sample>>(
+        &mut self,
+        case: Case,
+        mut f: impl FnMut() -> S,
+    ) -> io::Result<&Stats> {
+        for _ in 0..self.warmup {
+            f();
+        }
+        let mut times = vec![];
+        let mut contention = vec![];
+        for _ in 0..self.repetitions {
+            let sample = f().into();
+            let time = sample.time.as_secs_f64() * 1000.0;
+            self.write_sample(&case, time, &sample)?;
+            times.push(time);
+            contention.extend(sample.contention);
+        }
+        let stats = Stats::from_samples(&times).expect("at least one repetition");
+        info!(
+            "{}: mean {:.3} ms, stddev {:.3} ms over {} runs",
+            labels_text(&case),
+            stats.mean,
+            stats.stddev,
+            stats.samples
+        );
+        self.records.push(Record {
+            case,
+            stats,
+            samples: times,
+            contention,
+        });
+        Ok(&self.records.last().unwrap().stats)
+    }
+
+    // Writes the summary of every case to the configured output.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(samples) = self.samples.as_mut() {
+            samples.flush()?;
+        }
+        let mut output: Box<dyn Write> = match self.output.take() {
+            Some(file) => Box::new(file),
+            None => Box::new(io::stdout()),
+        };
+        match self.format {
+            OutputFormat::Csv => self.write_csv(&mut output)?,
+            OutputFormat::Json => self.write_json(&mut output)?,
+        }
+        output.flush()
+    }
+
+    // Samples are written as they come, so a long run that gets cut short
+    // still leaves them behind.
+    fn write_sample(&mut self, case: &Case, time: f64, sample: &Sample) -> io::Result<()> {
+        let file = match self.samples.as_mut() {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        if !self.samples_header {
+            let mut header = case
+                .labels
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .chain(["time"])
+                .chain(sample.extra.iter().map(|(key, _)| key.as_str()))
+                .collect::<Vec<_>>();
+            if STATS {
+                header.extend(CONTENTION_COLUMNS);
+            }
+            writeln!(file, "{}", header.join(","))?;
+            self.samples_header = true;
+        }
+        let mut row = case
+            .labels
+            .iter()
+            .map(|(_, value)| value.clone())
+            .chain([format!("{:.3}", time)])
+            .chain(sample.extra.iter().map(|(_, value)| value.clone()))
+            .collect::<Vec<_>>();
+        if STATS {
+            row.extend(contention_fields(sample.contention));
+        }
+        writeln!(file, "{}", row.join(","))
+    }
+
+    fn write_csv(&self, output: &mut impl Write) -> io::Result<()> {
+        for (key, value) in self.metadata.fields() {
+            writeln!(output, "# {}: {}", key, value)?;
+        }
+        let first = match self.records.first() {
+            Some(record) => record,
+            None => return Ok(()),
+        };
+        let mut header = first
+            .case
+            .labels
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>();
+        header.extend([
+            "samples",
+            "mean",
+            "median",
+            "stddev",
+            "min",
+            "max",
+            "p90",
+            "p99",
+            "ci95_low",
+            "ci95_high",
+        ]);
+        if STATS {
+            header.extend(CONTENTION_COLUMNS);
+        }
+        writeln!(output, "{}", header.join(","))?;
+        for record in &self.records {
+            let stats = &record.stats;
+            let mut row = record
+                .case
+                .labels
+                .iter()
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>();
+            row.push(stats.samples.to_string());
+            row.extend(
+                [
+                    stats.mean,
+                    stats.median,
+                    stats.stddev,
+                    stats.min,
+                    stats.max,
+                    stats.p90,
+                    stats.p99,
+                    stats.ci95.0,
+                    stats.ci95.1,
+                ]
+                .iter()
+                .map(|value| format!("{:.3}", value)),
+            );
+            if STATS {
+                row.extend(contention_fields(mean_contention(&record.contention)));
+            }
+            writeln!(output, "{}", row.join(","))?;
+        }
+        Ok(())
+    }
+
+    fn write_json(&self, output: &mut impl Write) -> io::Result<()> {
+        let metadata = self
+            .metadata
+            .fields()
+            .into_iter()
+            .map(|(key, value)| format!("{}: {}", json_string(key), json_string(&value)))
+            .collect::<Vec<_>>();
+        writeln!(output, "{{")?;
+        writeln!(output, "  \"metadata\": {{{}}},", metadata.join(", "))?;
+        writeln!(output, "  \"cases\": [")?;
+        for (i, record) in self.records.iter().enumerate() {
+            let labels = record
+                .case
+                .labels
+                .iter()
+                .map(|(key, value)| format!("{}: {}", json_string(key), json_string(value)))
+                .collect::<Vec<_>>();
+            let stats = &record.stats;
+            let samples = record
+                .samples
+                .iter()
+                .map(|time| format!("{:.3}", time))
+                .collect::<Vec<_>>();
+            write!(
+                output,
+                "    {{\"labels\": {{{}}}, \"samples\": {}, \"mean\": {:.3}, \"median\": {:.3}, \
+                 \"stddev\": {:.3}, \"min\": {:.3}, \"max\": {:.3}, \"p90\": {:.3}, \"p99\": {:.3}, \
+                 \"ci95\": [{:.3}, {:.3}], \"times\": [{}]{}}}",
+                labels.join(", "),
+                stats.samples,
+                stats.mean,
+                stats.median,
+                stats.stddev,
+                stats.min,
+                stats.max,
+                stats.p90,
+                stats.p99,
+                stats.ci95.0,
+                stats.ci95.1,
+                samples.join(", "),
+                contention_json(mean_contention(&record.contention))
+            )?;
+            writeln!(
+                output,
+                "{}",
+                if i + 1 < self.records.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(output, "  ]")?;
+        writeln!(output, "}}")
+    }
+}
+
+fn labels_text(case: &Case) -> String {
+    case.labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn command_line_overrides_config_file() {
+        let path = std::env::temp_dir().join(format!("bench-{}.conf", std::process::id()));
+        fs::write(&path, "# spinlock settings\nrepetitions = 3\nk = 1, 2, 4\n").unwrap();
+        let config = BenchConfig::from_args(
+            args(&format!(
+                "rw --config {} --repetitions=5 --lock-free --k 8",
+                path.display()
+            )),
+            &[],
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(["rw".to_string()], config.positional());
+        assert_eq!(Ok(5), config.value("repetitions", 10));
+        assert_eq!(Ok(vec![8]), config.list("k", &[1]));
+        assert_eq!(Ok(vec![16, 32]), config.list("n", &[16, 32]));
+        assert!(config.flag("lock-free"));
+        assert!(config.check_keys(&["k", "lock-free"]).is_ok());
+        assert!(config.check_keys(&["k"]).is_err());
+        assert!(config.value::<usize>("k", 0).is_ok());
+        assert!(BenchConfig::from_args(args("--k x"), &[])
+            .unwrap()
+            .list::<usize>("k", &[])
+            .is_err());
+    }
+
+    #[test]
+    fn counts_must_be_positive() {
+        let config = BenchConfig::from_args(args("--repetitions 0 --k 1,0"), &[]).unwrap();
+        assert!(config.count("repetitions", 10).is_err());
+        assert!(config.counts("k", &[1]).is_err());
+        assert_eq!(Ok(vec![2, 4]), config.counts("n", &[2, 4]));
+        assert!(Bench::new("test", &config).is_err());
+    }
+
+    #[test]
+    fn switches_leave_the_next_argument_alone() {
+        let config =
+            BenchConfig::from_args(args("--lock-free report out.csv --k 8"), &["lock-free"])
+                .unwrap();
+        assert!(config.flag("lock-free"));
+        assert_eq!(
+            ["report".to_string(), "out.csv".to_string()],
+            config.positional()
+        );
+        assert_eq!(Ok(vec![8]), config.list("k", &[1]));
+
+        let config =
+            BenchConfig::from_args(args("--lock-free=false report"), &["lock-free"]).unwrap();
+        assert!(!config.flag("lock-free"));
+        assert_eq!(["report".to_string()], config.positional());
+    }
+
+    #[test]
+    fn bad_output_paths_fail_before_running() {
+        let missing = std::env::temp_dir().join("no-such-dir").join("out.csv");
+        for key in ["output", "samples"] {
+            let config =
+                BenchConfig::from_args(args(&format!("--{} {}", key, missing.display())), &[])
+                    .unwrap();
+            assert!(Bench::new("test", &config).is_err());
+        }
+    }
+
+    #[test]
+    fn summary_statistics() {
+        let stats = Stats::from_samples(&[4.0, 2.0, 8.0, 6.0]).unwrap();
+        assert_eq!(4, stats.samples);
+        assert_eq!(5.0, stats.mean);
+        assert_eq!(5.0, stats.median);
+        assert!((stats.stddev - 2.582).abs() < 1e-3);
+        assert_eq!((2.0, 8.0), (stats.min, stats.max));
+        assert!((stats.p90 - 7.4).abs() < 1e-9);
+        // t = 3.182 for 3 degrees of freedom.
+        assert!((stats.ci95.1 - 9.108).abs() < 1e-3);
+        assert!((stats.mean - stats.ci95.0 - (stats.ci95.1 - stats.mean)).abs() < 1e-9);
+
+        let single = Stats::from_samples(&[3.0]).unwrap();
+        assert_eq!((3.0, 3.0), single.ci95);
+        assert!(Stats::from_samples(&[]).is_none());
+    }
+}
@@ -1,214 +1,397 @@
 use log::{debug, info};
 use rand::prelude::*;
-use sd::semaphore::Semaphore;
-use std::fs::File;
-use std::io::prelude::*;
-use std::sync::atomic::{AtomicI32, Ordering};
-use std::sync::{Arc, Mutex};
+use sd::bench::{Bench, BenchConfig, Case, Sample};
+use sd::bounded_buffer::{BoundedBuffer, PushError};
+use sd::contention::ContentionStats;
+#[cfg(target_os = "linux")]
+use sd::futex::FutexSemaphore;
+use sd::mpmc::MpmcQueue;
+use sd::report;
+use sd::semaphore::{Barrier, FairSemaphore, RawSemaphore, Semaphore};
+use std::env;
+use std::hint;
+use std::io;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+// How many integers the consumers process in every repetition.
+const CONSUMER_LIMIT: usize = 100_000;
+
+// Cases measured unless `--cases` says otherwise, as (n_p, n_c): the
+// number of producers and consumers, respectively.
+const CASES: [Threads; 7] = [
+    Threads(1, 1),
+    Threads(1, 2),
+    Threads(1, 4),
+    Threads(1, 8),
+    Threads(2, 1),
+    Threads(4, 1),
+    Threads(8, 1),
+];
+
+// Buffer lengths measured unless `--lengths` says otherwise. A new buffer
+// is created for every repetition.
+const VECTOR_LENGTHS: [usize; 5] = [1, 2, 4, 16, 32];
+
+// Producers and consumers of a case, written `n_p:n_c`.
+#[derive(Debug, Clone, Copy)]
+struct Threads(usize, usize);
+
+impl FromStr for Threads {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (n_p, n_c) = s
+            .split_once(':')
+            .ok_or_else(|| "expected producers:consumers".to_string())?;
+        let count = |n: &str| match n.parse::<usize>() {
+            Ok(0) => Err("every case needs producers and consumers".to_string()),
+            Ok(n) => Ok(n),
+            Err(e) => Err(e.to_string()),
+        };
+        Ok(Threads(count(n_p)?, count(n_c)?))
+    }
+}
 
 fn main() {
     // Set up env_logger so we can track what is happening
     // using environment variable RUST_LOG=debug
     env_logger::init();
 
-    // First, we will set the counter of consumed integers that
-    // will be shared across the threads. We do not need an ARC
-    // in this case since we will be using an atomic integer type.
-    static CONSUMER_COUNTER: AtomicI32 = AtomicI32::new(0);
-
-    const CONSUMER_LIMIT: i32 = 100_000;
-
-    // We can set up the cases in an array. Each case represents the number
-    // of consumers and producers, respectively, e.g. (n_c, n_p).
-    let cases = [(1, 1), (1, 2), (1, 4), (1, 8), (2, 1), (4, 1), (8, 1)];
-
-    // We will keep our records inside a vector and print it at the end of our run.
-    let mut results: Vec<String> = vec![];
-    let results_header = "n,n_p,n_c,time".to_string();
-    results.push(results_header);
-
-    // We can keep track of our buffer length with
-    // an array and create the buffer on each loop.
-    let vector_lengths = [1, 2, 4, 16, 32];
-    for (n_p, n_c) in cases {
-        for n in vector_lengths {
-            // We need will be using Semaphores to control
-            // how many consumers and producers should be running.
-            // We will also be using an ARC to share the semaphores
-            // between threads.
-            let empty_semaphore = Arc::new(Semaphore::new(n));
-            let full_semaphore = Arc::new(Semaphore::new(0));
-
-            for _ in 0..10 {
-                info!("[CASE] STARTING NEW CASE: n = {n}, n_p = {n_p}, n_c = {n_c}");
-                // We will be using a Mutex to ensure
-                // that only a single thread has access to the buffer
-                // and this will also enable us to pass the buffer inside
-                // an ARC to be shared between threads.
-                let vector_mutex: Arc<Mutex<Vec<i32>>> = Arc::new(Mutex::new(vec![0; n as usize]));
-
-                // We also need to have control over our threads.
-                // We can get that by creating a handler buffer so
-                // that we can wait all of them return.
-                let mut handles: Vec<JoinHandle<()>> = vec![];
-
-                // We can start keeping track of our time here.
-
-                let start = std::time::Instant::now();
-
-                // We will set up a handle buffer to handle both
-                // consumer and producer threads.
-                // let mut handles: Vec<JoinHandle<()>> = vec![];
-                // We need to clone the ARC of resources to make them
-                // available for our threads.
-                for _p in 0..n_p {
-                    debug!("[PRODUCER][{_p}] Starting");
-                    let producer_empty_semaphore = Arc::clone(&empty_semaphore);
-                    let producer_full_semaphore = Arc::clone(&full_semaphore);
-                    let producer_vector_mutex = Arc::clone(&vector_mutex);
-                    // We initialize a Producer thread that produces indefinitely.
-                    thread::spawn(move || {
-                        // Initialize random number generator
-                        let mut rng = rand::thread_rng();
-                        loop {
-                            // We need to clone both semaphores and the vector inside the ARCs.
-                            let empty = Arc::clone(&producer_empty_semaphore);
-                            let full = Arc::clone(&producer_full_semaphore);
-                            let vector_arc = Arc::clone(&producer_vector_mutex);
-                            // We need to make sure that we can take temporary ownership
-                            // of the vector. We only do this after we have the semaphores
-                            // with enough space.
-                            debug!("[PRODUCER][{_p}] Acquiring EMPTY");
-                            empty.wait();
-                            debug!("[PRODUCER][{_p}] EMPTY acquired successfully.");
-                            // Now we can lock the buffer Mutex.
-                            debug!("[PRODUCER][{_p}] Locking VECTOR_MUTEX.");
-                            let mut vector = vector_arc.lock().unwrap();
-                            debug!("[PRODUCER][{_p}] VECTOR_MUTEX locked successfully.");
-                            for i in 0..vector.len() {
-                                // If the position contains a zero, we fill the spot
-                                // with the current index.
-                                if vector[i] == 0 {
-                                    debug!("[PRODUCER][{_p}] Found empty space in {i}");
-                                    // We can now fill the empty space with the random number.
-                                    vector[i] = rng.gen_range(1..10_000_001);
-                                    debug!(
-                                        "[PRODUCER][{_p}] Filled position {i} with {}",
-                                        vector[i]
-                                    );
-                                    break;
-                                }
-                            }
-                            // We now drop the vector reference so that the buffer
-                            // Mutex is unlocked.
-                            debug!("[PRODUCER][{_p}] Unlocking VECTOR_MUTEX.");
-                            drop(vector);
-                            debug!("[PRODUCER][{_p}] VECTOR_MUTEX unlocked successfully.");
-                            // We now use the release method to signal that there is
-                            // an empty space inside the buffer.
-                            debug!("[PRODUCER][{_p}] Releasing FULL.");
-                            full.signal();
-                            debug!("[PRODUCER][{_p}] FULL released successfully.");
-                        }
-                    });
-                }
+    let mut config =
+        BenchConfig::from_args(env::args().skip(1), &["lock-free"]).unwrap_or_else(|e| usage(&e));
+    let result = match config.positional() {
+        [] => {
+            config.set_default("output", "./data/semaphore_results.csv");
+            run(&config)
+        }
+        [mode, files @ ..] if mode == "report" => report::run(files, &config),
+        _ => Err("the only mode is report".to_string()),
+    };
+    if let Err(e) = result {
+        usage(&e);
+    }
+}
 
-                for _c in 0..n_c {
-                    let consumer_empty_semaphore = Arc::clone(&empty_semaphore);
-                    let consumer_full_semaphore = Arc::clone(&full_semaphore);
-                    let consumer_vector_mutex = Arc::clone(&vector_mutex);
-
-                    // Spawn consumer thread and register the handle on handles buffer.
-                    handles.push(thread::spawn(move || {
-                        debug!("[CONSUMER][{_c}] Starting.");
-                        while CONSUMER_COUNTER.load(Ordering::SeqCst) < CONSUMER_LIMIT {
-                            // We need to clone both semaphores and the vector inside the ARCs.
-                            let empty = Arc::clone(&consumer_empty_semaphore);
-                            let full = Arc::clone(&consumer_full_semaphore);
-                            let vector_arc = Arc::clone(&consumer_vector_mutex);
-                            // We need to make sure that we can take temporary ownership
-                            // of the vector. We only do this after we have the semaphores
-                            // with enough space.
-                            debug!("[CONSUMER][{_c}] Acquiring FULL");
-                            full.wait();
-                            debug!("[CONSUMER][{_c}] FULL acquired successfully");
-                            // Now we can lock the buffer Mutex.
-                            debug!("[CONSUMER][{_c}] Locking VECTOR_MUTEX");
-                            let mut vector = vector_arc.lock().unwrap();
-                            debug!("[CONSUMER][{_c}] VECTOR_MUTEX locked successfully");
-                            // let mut to_consume = -1;
-                            for i in 0..vector.len() {
-                                // If the position does not contain a zero
-                                // we consume it. This means we will process it
-                                // and fill the space with a zero.
-                                if vector[i] != 0 {
-                                    debug!(
-                                        "[CONSUMER][{_c}] Consuming {} at position {}",
-                                        vector[i], i
-                                    );
-                                    is_prime(vector[i]);
-                                    debug!("[CONSUMER][{_c}] Consumed {} successfully", vector[i]);
-                                    debug!("[CONSUMER][{_c}] Emptying position {}.", i);
-                                    vector[i] = 0;
-                                    if vector[i] == 0 {
-                                        debug!("[CONSUMER][{_c}] Position {i} is now empty");
-                                    }
-                                    // CONSUMER_COUNTER.fetch_add/* ( */1, Ordering::Acquire);
-                                    debug!("[CONSUMER][{_c}] Updating CONSUMER_COUNTER.");
-                                    CONSUMER_COUNTER
-                                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| {
-                                            Some(x + 1)
-                                        })
-                                        .unwrap();
-                                    debug!(
-                                        "[CONSUMER][{_c}] CONSUMER_COUNTER = {}",
-                                        CONSUMER_COUNTER.load(Ordering::SeqCst)
-                                    );
-                                    break;
-                                }
-                            }
-                            // We now drop the vector reference so that the buffer
-                            // Mutex is unlocked.
-                            debug!("[CONSUMER][{_c}] Unlocking VECTOR_MUTEX");
-                            drop(vector);
-                            debug!("[CONSUMER][{_c}] VECTOR_MUTEX was unlocked successfully");
-                            // We now use the release method to signal that there is
-                            // an empty space inside the buffer.
-                            debug!("[CONSUMER][{_c}] Releasing EMPTY");
-                            empty.signal();
-                            debug!("[CONSUMER][{_c}] EMPTY released successfully");
-                        }
-                        debug!("[CONSUMER][{_c}] Finishing.");
-                    }));
-                }
+fn usage(error: &str) -> ! {
+    eprintln!("semaphore: {error}");
+    eprintln!("usage: semaphore [--config FILE] [--key value...]");
+    eprintln!("       semaphore report FILE... [--output DIR]");
+    process::exit(2);
+}
 
-                // Wait for all threads to finish.
-                for handle in handles {
-                    handle.join().unwrap();
-                }
-                // Loop until we hit the CONSUMER_LIMIT.
-                // while CONSUMER_COUNTER.load(Ordering::SeqCst) < consumer_limit {}
-                // When the loop is over, we can consider our finishing time.
-                let elapsed = start.elapsed();
-
-                // We can register the results and reset the consumer counter.
-                let result = format!("{},{},{},{}", n, n_p, n_c, elapsed.as_millis());
-                info!("{result}");
-                results.push(result);
-
-                // We need to reset the CONSUMER_COUNTER.
-                CONSUMER_COUNTER.store(0, Ordering::SeqCst);
+// Settings besides the common ones:
+//   cases      n_p:n_c pairs
+//   lengths    buffer lengths
+//   buffers    std, fair, futex (Linux only) and lockfree
+//              (default: std,fair and futex where there is one)
+//   lock-free  same as adding lockfree to the buffers
+//   limit      integers the consumers process in every repetition
+fn run(config: &BenchConfig) -> Result<(), String> {
+    config.check_keys(&["cases", "lengths", "buffers", "lock-free", "limit"])?;
+    let cases = config.list("cases", &CASES)?;
+    let lengths = config.counts("lengths", &VECTOR_LENGTHS)?;
+    let mut defaults = vec!["std".to_string(), "fair".to_string()];
+    if cfg!(target_os = "linux") {
+        defaults.push("futex".to_string());
+    }
+    let mut buffers = config.list("buffers", &defaults)?;
+    // With --lock-free the same cases also run on a lock-free queue, where
+    // threads spin instead of sleeping while the buffer is full or empty.
+    if config.flag("lock-free") && !buffers.iter().any(|b| b == "lockfree") {
+        buffers.push("lockfree".to_string());
+    }
+    let limit = config.value("limit", CONSUMER_LIMIT)?;
+
+    let mut bench = Bench::new("semaphore", config)?;
+    // Every case runs with the standard semaphore and the FIFO-fair one, to
+    // see what fairness costs in throughput, and the futex one, to see what
+    // going through a Mutex and Condvar costs.
+    for name in &buffers {
+        let ran = match name.as_str() {
+            "std" => run_cases::<BoundedBuffer<i32, Semaphore>>(
+                &mut bench, name, &cases, &lengths, limit,
+            ),
+            "fair" => run_cases::<BoundedBuffer<i32, FairSemaphore>>(
+                &mut bench, name, &cases, &lengths, limit,
+            ),
+            #[cfg(target_os = "linux")]
+            "futex" => run_cases::<BoundedBuffer<i32, FutexSemaphore>>(
+                &mut bench, name, &cases, &lengths, limit,
+            ),
+            "lockfree" => run_cases::<LockFreeBuffer>(&mut bench, name, &cases, &lengths, limit),
+            name => return Err(format!("unknown buffer '{name}'")),
+        };
+        ran.map_err(|e| format!("writing samples: {e}"))?;
+    }
+    bench.finish().map_err(|e| e.to_string())
+}
+
+// Everything a repetition shares between its threads. A new one is made
+// for every repetition, so nothing carries over from the previous run.
+struct Run {
+    limit: usize,
+    // Items consumers have committed to take, see `claim`.
+    claimed: AtomicUsize,
+    consumed: AtomicUsize,
+    produced: AtomicUsize,
+}
+
+impl Run {
+    // Consumers reserve an item before popping it, so that together they
+    // never take more than the limit.
+    fn claim(&self) -> bool {
+        self.claimed.fetch_add(1, Ordering::SeqCst) < self.limit
+    }
+}
+
+// The buffer producers and consumers talk through.
+trait Buffer: Send + Sync + 'static {
+    fn with_capacity(capacity: usize) -> Self;
+
+    // Waits while the buffer is full. Hands the value back once the
+    // buffer is closed.
+    fn push(&self, value: i32) -> Result<(), i32>;
+
+    // Waits while the buffer is empty. Returns None once it is closed and
+    // nothing is left.
+    fn pop(&self) -> Option<i32>;
+
+    fn close(&self);
+
+    // Throws away whatever is left and says how much that was.
+    fn drain(&self) -> usize;
+
+    fn stats(&self) -> Option<ContentionStats> {
+        None
+    }
+}
+
+impl<S: RawSemaphore + 'static> Buffer for BoundedBuffer<i32, S> {
+    fn with_capacity(capacity: usize) -> Self {
+        BoundedBuffer::with_semaphores(capacity)
+    }
+
+    fn push(&self, value: i32) -> Result<(), i32> {
+        BoundedBuffer::push(self, value).map_err(|e| match e {
+            PushError::Full(value) | PushError::Closed(value) => value,
+        })
+    }
+
+    fn pop(&self) -> Option<i32> {
+        BoundedBuffer::pop(self)
+    }
+
+    fn close(&self) {
+        BoundedBuffer::close(self)
+    }
+
+    fn drain(&self) -> usize {
+        std::iter::from_fn(|| self.try_pop().ok()).count()
+    }
+
+    fn stats(&self) -> Option<ContentionStats> {
+        Some(BoundedBuffer::stats(self))
+    }
+}
+
+// The lock-free queue never blocks, so waiting threads spin on it and
+// check the closed flag in between.
+struct LockFreeBuffer {
+    queue: MpmcQueue<i32>,
+    closed: AtomicBool,
+}
+
+impl Buffer for LockFreeBuffer {
+    fn with_capacity(capacity: usize) -> Self {
+        LockFreeBuffer {
+            queue: MpmcQueue::new(capacity),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    fn push(&self, mut value: i32) -> Result<(), i32> {
+        let mut attempts = 0;
+        loop {
+            if self.closed.load(Ordering::Relaxed) {
+                return Err(value);
+            }
+            match self.queue.push(value) {
+                Ok(()) => return Ok(()),
+                Err(back) => value = back,
+            }
+            backoff(&mut attempts);
+        }
+    }
+
+    fn pop(&self) -> Option<i32> {
+        let mut attempts = 0;
+        loop {
+            if let Some(value) = self.queue.pop() {
+                return Some(value);
+            }
+            if self.closed.load(Ordering::Relaxed) {
+                return None;
             }
+            backoff(&mut attempts);
+        }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    fn drain(&self) -> usize {
+        std::iter::from_fn(|| self.queue.pop()).count()
+    }
+}
+
+fn run_cases<B: Buffer>(
+    bench: &mut Bench,
+    name: &str,
+    cases: &[Threads],
+    lengths: &[usize],
+    limit: usize,
+) -> io::Result<()> {
+    for &Threads(n_p, n_c) in cases {
+        for &n in lengths {
+            info!("[CASE] STARTING NEW CASE: n = {n}, n_p = {n_p}, n_c = {n_c}");
+            let case = Case::new()
+                .with("n", n)
+                .with("n_p", n_p)
+                .with("n_c", n_c)
+                .with("semaphore", name);
+            bench.run(case, || run_case::<B>(n, n_p, n_c, limit))?;
         }
     }
+    Ok(())
+}
+
+// Runs a single repetition on a fresh buffer of size n. The time is taken
+// once the consumers are done; afterwards the buffer is closed so the
+// producers stop, and every thread is joined before returning.
+fn run_case<B: Buffer>(n: usize, n_p: usize, n_c: usize, limit: usize) -> Sample {
+    // We will be using an ARC to share the buffer and the run
+    // between threads.
+    let buffer = Arc::new(B::with_capacity(n));
+    let run = Arc::new(Run {
+        limit,
+        claimed: AtomicUsize::new(0),
+        consumed: AtomicUsize::new(0),
+        produced: AtomicUsize::new(0),
+    });
+
+    // Every thread waits here until all of them were spawned, and the
+    // first one through starts the clock, so spawning is left out of the
+    // time.
+    let ready = Arc::new(Barrier::new(n_p + n_c));
+    let started = Arc::new(OnceLock::new());
+
+    // We also need to have control over our threads.
+    // We can get that by keeping their handles so
+    // that we can wait all of them return.
+    let producers: Vec<JoinHandle<()>> = (0..n_p)
+        .map(|_p| {
+            let buffer = Arc::clone(&buffer);
+            let run = Arc::clone(&run);
+            let ready = Arc::clone(&ready);
+            let started = Arc::clone(&started);
+            // We initialize a Producer thread that produces until
+            // the buffer is closed.
+            thread::spawn(move || {
+                ready.wait();
+                started.get_or_init(Instant::now);
+                debug!("[PRODUCER][{_p}] Starting");
+                // Initialize random number generator
+                let mut rng = rand::thread_rng();
+                loop {
+                    let value = rng.gen_range(1..10_000_001);
+                    // Pushing waits while the buffer is full.
+                    debug!("[PRODUCER][{_p}] Pushing {value}");
+                    if buffer.push(value).is_err() {
+                        break;
+                    }
+                    run.produced.fetch_add(1, Ordering::SeqCst);
+                    debug!("[PRODUCER][{_p}] Pushed {value} successfully.");
+                }
+                debug!("[PRODUCER][{_p}] Finishing.");
+            })
+        })
+        .collect();
+
+    let consumers: Vec<JoinHandle<()>> = (0..n_c)
+        .map(|_c| {
+            let buffer = Arc::clone(&buffer);
+            let run = Arc::clone(&run);
+            let ready = Arc::clone(&ready);
+            let started = Arc::clone(&started);
+            thread::spawn(move || {
+                ready.wait();
+                started.get_or_init(Instant::now);
+                debug!("[CONSUMER][{_c}] Starting.");
+                while run.claim() {
+                    // Popping waits while the buffer is empty. Producers
+                    // keep going until we are done, so there is always
+                    // something coming.
+                    let value = match buffer.pop() {
+                        Some(value) => value,
+                        None => break,
+                    };
+                    debug!("[CONSUMER][{_c}] Consuming {value}");
+                    is_prime(value);
+                    run.consumed.fetch_add(1, Ordering::SeqCst);
+                    debug!("[CONSUMER][{_c}] Consumed {value} successfully");
+                }
+                debug!("[CONSUMER][{_c}] Finishing.");
+            })
+        })
+        .collect();
+
+    // Wait for all consumers to finish.
+    for handle in consumers {
+        handle.join().unwrap();
+    }
+    // When the consumers are done, we can consider our finishing time.
+    let elapsed = started.get().expect("no thread ran").elapsed();
+
+    // Now we stop the producers and wait for them too, so they do not
+    // compete with the next repetition.
+    buffer.close();
+    for handle in producers {
+        handle.join().unwrap();
+    }
 
-    // We will write results to a file.
-    let mut file = File::create("./data/semaphore_results.csv")
-        .expect("Could not open '/.data/semaphore_results.csv'.");
-    for entry in results.into_iter().skip(1) {
-        println!("Result: {}", entry);
-        file.write_all(format!("{entry}\n").as_bytes()).unwrap();
+    let consumed = run.consumed.load(Ordering::SeqCst);
+    assert_eq!(
+        limit, consumed,
+        "consumers processed {consumed} integers instead of {limit}"
+    );
+    let left = buffer.drain();
+    assert_eq!(
+        run.produced.load(Ordering::SeqCst),
+        consumed + left,
+        "integers went missing in the buffer"
+    );
+    match buffer.stats() {
+        Some(stats) => Sample::from(elapsed).with_contention(stats),
+        None => Sample::from(elapsed),
+    }
+}
+
+// Spins for a few rounds, then starts giving the CPU away so waiting
+// threads do not starve the ones that would let them make progress.
+fn backoff(attempts: &mut u32) {
+    if *attempts < 6 {
+        for _ in 0..1 << *attempts {
+            hint::spin_loop();
+        }
+        *attempts += 1;
+    } else {
+        thread::yield_now();
     }
 }
 
@@ -226,5 +409,5 @@ fn is_prime(int: i32) -> bool {
         }
     }
     println!("{} is prime", int);
-    return true;
+    true
 }
@@ -1,67 +1,447 @@
 use rand::prelude::*;
 use rand::SeedableRng;
-use sd::spinlock::Spinlock;
-use std::sync::Arc;
+use sd::bench::{Bench, BenchConfig, Case, Sample};
+use sd::clh::RawClhLock;
+use sd::contention::ContentionStats;
+#[cfg(target_os = "linux")]
+use sd::futex::RawFutexLock;
+use sd::lock::{Lock, RawLock};
+use sd::mcs::RawMcsLock;
+use sd::report;
+use sd::semaphore::Barrier;
+use sd::spinlock::{RawSpinlock, RwPreference, RwSpinlock, SeqLock, SpinStrategy, Spinlock};
+use sd::ticket::RawTicketLock;
+use std::env;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
+use std::time::{Duration, Instant};
 
 const SEED: u64 = 42;
+// Thread counts measured unless `--k` says otherwise.
+const K_VALS: [usize; 9] = [1, 2, 4, 8, 16, 32, 64, 128, 256];
+// Operations per case in the read/write benchmark, split among the threads.
+const RW_OPS: usize = 1_000_000;
+
+// Data shared in the read/write benchmark. Writers bump every field, so a
+// reader seeing different values caught a write halfway through.
+type Record = [u64; 8];
+
+// How the threads combine their parts into the total: one of our locks,
+// the standard Mutex, a single atomic counter or a counter per thread.
+#[derive(Debug, Clone, Copy)]
+enum Algorithm {
+    Spin(SpinStrategy),
+    Ticket,
+    Mcs,
+    Clh,
+    #[cfg(target_os = "linux")]
+    Futex,
+    Mutex,
+    Atomic,
+    Sharded,
+}
+
+impl Algorithm {
+    fn all() -> Vec<Algorithm> {
+        let mut all = SpinStrategy::ALL.map(Algorithm::Spin).to_vec();
+        all.extend([
+            Algorithm::Ticket,
+            Algorithm::Mcs,
+            Algorithm::Clh,
+            #[cfg(target_os = "linux")]
+            Algorithm::Futex,
+            Algorithm::Mutex,
+            Algorithm::Atomic,
+            Algorithm::Sharded,
+        ]);
+        all
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Algorithm::Spin(strategy) => write!(f, "{strategy}"),
+            Algorithm::Ticket => write!(f, "ticket"),
+            Algorithm::Mcs => write!(f, "mcs"),
+            Algorithm::Clh => write!(f, "clh"),
+            #[cfg(target_os = "linux")]
+            Algorithm::Futex => write!(f, "futex"),
+            Algorithm::Mutex => write!(f, "mutex"),
+            Algorithm::Atomic => write!(f, "atomic"),
+            Algorithm::Sharded => write!(f, "sharded"),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ticket" => Ok(Algorithm::Ticket),
+            "mcs" => Ok(Algorithm::Mcs),
+            "clh" => Ok(Algorithm::Clh),
+            #[cfg(target_os = "linux")]
+            "futex" => Ok(Algorithm::Futex),
+            "mutex" => Ok(Algorithm::Mutex),
+            "atomic" => Ok(Algorithm::Atomic),
+            "sharded" => Ok(Algorithm::Sharded),
+            s => s.parse().map(Algorithm::Spin),
+        }
+    }
+}
 
 fn main() {
-    let k_vals = [1, 2, 4, 8, 16, 32, 64, 128, 256];
-    let n_vals = [10_i32.pow(7), 10_i32.pow(8), 10_i32.pow(9)];
+    env_logger::init();
+    let config = BenchConfig::from_args(env::args().skip(1), &[]).unwrap_or_else(|e| usage(&e));
+    let result = match config.positional() {
+        [] => sum_benchmark(&config),
+        [mode] if mode == "rw" => rw_benchmark(&config),
+        [mode, files @ ..] if mode == "report" => report::run(files, &config),
+        _ => Err("the modes are rw and report".to_string()),
+    };
+    if let Err(e) = result {
+        usage(&e);
+    }
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("spinlock: {error}");
+    eprintln!("usage: spinlock [rw] [--config FILE] [--key value...]");
+    eprintln!("       spinlock report FILE... [--output DIR]");
+    process::exit(2);
+}
+
+// Sums n random numbers split among k threads, which add their part to a
+// shared total behind the lock. Settings besides the common ones:
+//   locks  spin strategy names, ticket, mcs, clh, futex (Linux only),
+//          mutex, atomic and sharded (default: all)
+//   k      thread counts
+//   n      vector lengths
+fn sum_benchmark(config: &BenchConfig) -> Result<(), String> {
+    config.check_keys(&["locks", "k", "n"])?;
+    let algorithms = config.list("locks", &Algorithm::all())?;
+    let k_vals = config.counts("k", &K_VALS)?;
+    let n_vals = config.counts("n", &[10_usize.pow(7), 10_usize.pow(8), 10_usize.pow(9)])?;
+    let mut bench = Bench::new("spinlock", config)?;
     for n in n_vals {
-        let vector: Arc<Vec<i8>> = Arc::new(populate_random_i8(n as usize));
-        for k in k_vals {
-            for _ in 0..10 {
-                let vec = Arc::clone(&vector);
-                run_case(k, n, &*vec);
+        // Every run has to come up with the same total as a single thread.
+        let expected = sum_range(0..n);
+        for algorithm in &algorithms {
+            for &k in &k_vals {
+                let case = Case::new()
+                    .with("k", k)
+                    .with("n", n)
+                    .with("lock", algorithm);
+                bench
+                    .run(case, || match *algorithm {
+                        Algorithm::Spin(strategy) => run_case(
+                            k,
+                            n,
+                            expected,
+                            Lock::with_raw(RawSpinlock::with_strategy(strategy), 0),
+                        ),
+                        Algorithm::Ticket => {
+                            run_case(k, n, expected, Lock::<RawTicketLock, _>::new(0))
+                        }
+                        Algorithm::Mcs => run_case(k, n, expected, Lock::<RawMcsLock, _>::new(0)),
+                        Algorithm::Clh => run_case(k, n, expected, Lock::<RawClhLock, _>::new(0)),
+                        #[cfg(target_os = "linux")]
+                        Algorithm::Futex => {
+                            run_case(k, n, expected, Lock::<RawFutexLock, _>::new(0))
+                        }
+                        Algorithm::Mutex => run_case(k, n, expected, Mutex::new(0)),
+                        Algorithm::Atomic => run_case(k, n, expected, AtomicI64::new(0)),
+                        Algorithm::Sharded => run_case(k, n, expected, Sharded::new(k)),
+                    })
+                    .map_err(samples_error)?;
             }
         }
     }
+    bench.finish().map_err(|e| e.to_string())
 }
 
-fn run_case(k: i32, n: i32, random_vector: &Vec<i8>) {
-    let step_size: usize = (n / k) as usize;
-    let sum = Arc::new(Spinlock::new(0));
+// Keeps the sum and max_wait, the longest any thread took to add its part
+// in microseconds. FIFO locks should keep it low even with many threads.
+fn run_case(k: usize, n: usize, expected: i64, sum: impl Accumulator) -> Sample {
+    let step_size = n.div_ceil(k).max(1);
+    // The clock starts when the first thread gets past the barrier, once
+    // every thread is up, so spawning them is not part of the time.
+    let ready = Barrier::new((0..n).step_by(step_size).len());
+    let started = OnceLock::new();
 
-    let now = std::time::Instant::now();
+    // Each thread generates and adds up its own part of the numbers, so
+    // the whole vector never has to be in memory.
+    let max_wait = thread::scope(|scope| {
+        let handles = (0..n)
+            .step_by(step_size)
+            .enumerate()
+            .map(|(thread, start)| {
+                let sum = &sum;
+                let (ready, started) = (&ready, &started);
+                scope.spawn(move || {
+                    ready.wait();
+                    started.get_or_init(Instant::now);
+                    let local_sum = sum_range(start..n.min(start + step_size));
+                    let waiting = Instant::now();
+                    sum.add(thread, local_sum);
+                    waiting.elapsed()
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .max()
+            .unwrap_or(Duration::ZERO)
+    });
 
-    // let vec = Arc::clone(random_vector);
-    let mut handles = vec![];
-    for chunks in random_vector.chunks(step_size) {
-        let sum = Arc::clone(&sum);
-        let chunk = chunks.to_owned();
-        let handle = thread::spawn(move || {
-            let local_sum = sum_inside_thread(&chunk);
-            sum.acquire();
-            sum.set(*sum.get() + local_sum).unwrap();
-            sum.release();
-        });
-        handles.push(handle);
+    let elapsed = started.get().expect("no thread ran").elapsed();
+    let contention = sum.stats();
+    let sum = sum.total();
+    assert_eq!(expected, sum, "k = {k} threads added up to the wrong total");
+    let sample = Sample::from(elapsed)
+        .with("sum", sum)
+        .with("max_wait", max_wait.as_micros());
+    match contention {
+        Some(stats) => sample.with_contention(stats),
+        None => sample,
     }
-    for handle in handles {
-        handle.join().unwrap();
+}
+
+trait Accumulator: Sync {
+    // Adds the part summed by the given thread, numbered from 0 to k - 1.
+    fn add(&self, thread: usize, value: i64);
+    fn total(self) -> i64;
+
+    fn stats(&self) -> Option<ContentionStats> {
+        None
     }
+}
 
-    let elapsed = now.elapsed();
-    println!("{},{},{:?},{}", k, n, sum.get(), elapsed.as_millis());
+impl<L: RawLock> Accumulator for Lock<L, i64> {
+    fn add(&self, _thread: usize, value: i64) {
+        *self.lock() += value;
+    }
+
+    fn total(self) -> i64 {
+        self.into_inner()
+    }
+
+    fn stats(&self) -> Option<ContentionStats> {
+        self.raw().stats()
+    }
 }
 
-fn sum_inside_thread(vector: &[i8]) -> i32 {
-    let mut local_sum: i32 = 0;
-    for i in 0..vector.len() {
-        let value_as_i32 = vector[i] as i32;
-        local_sum += value_as_i32;
+impl Accumulator for Mutex<i64> {
+    fn add(&self, _thread: usize, value: i64) {
+        *self.lock().unwrap() += value;
+    }
+
+    fn total(self) -> i64 {
+        self.into_inner().unwrap()
+    }
+}
+
+impl Accumulator for AtomicI64 {
+    fn add(&self, _thread: usize, value: i64) {
+        self.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn total(self) -> i64 {
+        self.into_inner()
     }
-    local_sum
 }
 
-fn populate_random_i8(size: usize) -> Vec<i8> {
-    let mut vector = vec![0; size];
-    let mut rng = StdRng::seed_from_u64(SEED);
+// A counter per thread, each on its own cache line so threads never touch
+// each other's, added up once they are all done.
+struct Sharded {
+    counters: Box<[Padded]>,
+}
 
-    for i in 0..vector.len() {
-        vector[i] = rng.gen_range(-100..101);
+#[repr(align(64))]
+struct Padded(AtomicI64);
+
+impl Sharded {
+    fn new(k: usize) -> Self {
+        Sharded {
+            counters: (0..k).map(|_| Padded(AtomicI64::new(0))).collect(),
+        }
     }
-    vector.to_vec()
+}
+
+impl Accumulator for Sharded {
+    fn add(&self, thread: usize, value: i64) {
+        self.counters[thread].0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn total(self) -> i64 {
+        self.counters
+            .into_vec()
+            .into_iter()
+            .map(|counter| counter.0.into_inner())
+            .sum()
+    }
+}
+
+// `spinlock rw` has k threads read or update a Record behind an exclusive
+// Spinlock, a RwSpinlock preferring readers or writers and a SeqLock.
+// Settings besides the common ones:
+//   reads  percentages of operations that only read
+//   k      thread counts
+//   ops    operations per case, split among the threads
+fn rw_benchmark(config: &BenchConfig) -> Result<(), String> {
+    config.check_keys(&["reads", "k", "ops"])?;
+    let read_percentages = config.list("reads", &[0, 50, 90, 99, 100])?;
+    let k_vals = config.counts("k", &K_VALS)?;
+    let ops = config.value("ops", RW_OPS)?;
+    let mut bench = Bench::new("spinlock-rw", config)?;
+    for read_percentage in read_percentages {
+        for &k in &k_vals {
+            let case = |lock| {
+                Case::new()
+                    .with("k", k)
+                    .with("n", ops)
+                    .with("read%", read_percentage)
+                    .with("lock", lock)
+            };
+            bench
+                .run(case("spinlock"), || {
+                    run_rw_case(k, ops, read_percentage, Spinlock::new(Record::default()))
+                })
+                .map_err(samples_error)?;
+            bench
+                .run(case("rw-readers"), || {
+                    run_rw_case(
+                        k,
+                        ops,
+                        read_percentage,
+                        RwSpinlock::with_preference(Record::default(), RwPreference::Readers),
+                    )
+                })
+                .map_err(samples_error)?;
+            bench
+                .run(case("rw-writers"), || {
+                    run_rw_case(
+                        k,
+                        ops,
+                        read_percentage,
+                        RwSpinlock::with_preference(Record::default(), RwPreference::Writers),
+                    )
+                })
+                .map_err(samples_error)?;
+            bench
+                .run(case("seqlock"), || {
+                    run_rw_case(k, ops, read_percentage, SeqLock::new(Record::default()))
+                })
+                .map_err(samples_error)?;
+        }
+    }
+    bench.finish().map_err(|e| e.to_string())
+}
+
+fn samples_error(e: io::Error) -> String {
+    format!("writing samples: {e}")
+}
+
+trait SharedRecord: Send + Sync {
+    fn read(&self) -> Record;
+    fn update(&self);
+}
+
+impl SharedRecord for Spinlock<Record> {
+    fn read(&self) -> Record {
+        *self.lock()
+    }
+
+    fn update(&self) {
+        self.lock().iter_mut().for_each(|field| *field += 1);
+    }
+}
+
+impl SharedRecord for RwSpinlock<Record> {
+    fn read(&self) -> Record {
+        *RwSpinlock::read(self)
+    }
+
+    fn update(&self) {
+        self.write().iter_mut().for_each(|field| *field += 1);
+    }
+}
+
+impl SharedRecord for SeqLock<Record> {
+    fn read(&self) -> Record {
+        SeqLock::read(self)
+    }
+
+    fn update(&self) {
+        SeqLock::update(self, |record| {
+            record.iter_mut().for_each(|field| *field += 1)
+        });
+    }
+}
+
+fn run_rw_case(
+    k: usize,
+    ops: usize,
+    read_percentage: u32,
+    shared: impl SharedRecord + 'static,
+) -> Duration {
+    let shared = Arc::new(shared);
+    // Timed like the sum benchmark, from the first thread past the barrier.
+    let ready = Arc::new(Barrier::new(k));
+    let started = Arc::new(OnceLock::new());
+
+    let mut handles = vec![];
+    for i in 0..k {
+        let shared = Arc::clone(&shared);
+        let ready = Arc::clone(&ready);
+        let started = Arc::clone(&started);
+        let handle = thread::spawn(move || {
+            ready.wait();
+            started.get_or_init(Instant::now);
+            let mut rng = StdRng::seed_from_u64(SEED + i as u64);
+            let mut updates = 0;
+            for _ in 0..ops / k {
+                if rng.gen_range(0..100) < read_percentage {
+                    let record = shared.read();
+                    assert!(record.iter().all(|field| *field == record[0]));
+                } else {
+                    shared.update();
+                    updates += 1;
+                }
+            }
+            updates
+        });
+        handles.push(handle);
+    }
+    let updates: u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+
+    let elapsed = started.get().expect("no thread ran").elapsed();
+    assert_eq!([updates; 8], shared.read());
+    elapsed
+}
+
+// The numbers to add up are generated in blocks of BLOCK, each from its
+// own seed, so any part of them can be produced without the rest.
+const BLOCK: usize = 1 << 12;
+
+// Sum of the numbers at positions `range`, the same however it is split.
+fn sum_range(range: Range<usize>) -> i64 {
+    (range.start / BLOCK..range.end.div_ceil(BLOCK))
+        .flat_map(|block| {
+            let mut rng = StdRng::seed_from_u64(SEED + block as u64);
+            (0..BLOCK).map(move |_| rng.gen_range(-100..101_i8))
+        })
+        .skip(range.start % BLOCK)
+        .take(range.len())
+        .map(i64::from)
+        .sum()
 }
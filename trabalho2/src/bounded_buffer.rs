@@ -0,0 +1,295 @@
+use crate::contention::ContentionStats;
+use crate::semaphore::{RawSemaphore, Semaphore};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// A fixed-size FIFO queue shared by producer and consumer threads.
+// `empty` counts free slots and `full` counts stored items, so producers
+// block while the buffer is full and consumers while it is empty; the ring
+// itself is only locked for the moment it takes to move an item in or out.
+//
+// Closing the buffer makes every push fail and lets consumers drain what
+// is left, after which `pop` returns None.
+#[derive(Debug)]
+pub struct BoundedBuffer<T, S: RawSemaphore = Semaphore> {
+    ring: Mutex<Ring<T>>,
+    empty: S,
+    full: S,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushError<T> {
+    // The buffer stayed full, the value is handed back.
+    Full(T),
+    Closed(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopError {
+    Empty,
+    // Closed and nothing left to take.
+    Closed,
+}
+
+#[derive(Debug)]
+struct Ring<T> {
+    slots: Box<[Option<T>]>,
+    head: usize,
+    len: usize,
+    closed: bool,
+}
+
+impl<T> Ring<T> {
+    fn push(&mut self, value: T) {
+        let tail = (self.head + self.len) % self.slots.len();
+        self.slots[tail] = Some(value);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let value = self.slots[self.head].take();
+        self.head = (self.head + 1) % self.slots.len();
+        self.len -= 1;
+        value
+    }
+}
+
+impl<T> BoundedBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self::with_semaphores(capacity)
+    }
+}
+
+impl<T, S: RawSemaphore> BoundedBuffer<T, S> {
+    // Same as `new`, using S for the free slot and item counts.
+    pub fn with_semaphores(capacity: usize) -> Self {
+        assert!(capacity > 0, "a bounded buffer needs at least one slot");
+        BoundedBuffer {
+            ring: Mutex::new(Ring {
+                slots: (0..capacity).map(|_| None).collect(),
+                head: 0,
+                len: 0,
+                closed: false,
+            }),
+            empty: S::new(capacity),
+            full: S::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.lock().unwrap().slots.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.lock().unwrap().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Blocks while the buffer is full. Fails only if it gets closed.
+    pub fn push(&self, value: T) -> Result<(), PushError<T>> {
+        self.empty.wait();
+        self.store(value)
+    }
+
+    pub fn try_push(&self, value: T) -> Result<(), PushError<T>> {
+        if !self.empty.try_wait() {
+            return Err(self.full_or_closed(value));
+        }
+        self.store(value)
+    }
+
+    pub fn push_timeout(&self, value: T, timeout: Duration) -> Result<(), PushError<T>> {
+        if !self.empty.wait_timeout(timeout) {
+            return Err(self.full_or_closed(value));
+        }
+        self.store(value)
+    }
+
+    // Blocks while the buffer is empty. Returns None once it is closed and
+    // every item has been taken.
+    pub fn pop(&self) -> Option<T> {
+        self.full.wait();
+        self.take()
+    }
+
+    pub fn try_pop(&self) -> Result<T, PopError> {
+        if !self.full.try_wait() {
+            return Err(self.empty_or_closed());
+        }
+        self.take().ok_or(PopError::Closed)
+    }
+
+    pub fn pop_timeout(&self, timeout: Duration) -> Result<T, PopError> {
+        if !self.full.wait_timeout(timeout) {
+            return Err(self.empty_or_closed());
+        }
+        self.take().ok_or(PopError::Closed)
+    }
+
+    // Wakes up every blocked producer and consumer. Items already stored
+    // can still be popped.
+    pub fn close(&self) {
+        let mut ring = self.ring.lock().unwrap();
+        if ring.closed {
+            return;
+        }
+        ring.closed = true;
+        drop(ring);
+        // One waiter of each side is woken here, and each of them passes
+        // the permit on before giving up, see `store` and `take`.
+        self.empty.signal();
+        self.full.signal();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.ring.lock().unwrap().closed
+    }
+
+    // Producers waiting for a free slot and consumers waiting for an item,
+    // added up.
+    pub fn stats(&self) -> ContentionStats {
+        self.empty.stats().merge(self.full.stats())
+    }
+
+    // Called holding a free slot permit.
+    fn store(&self, value: T) -> Result<(), PushError<T>> {
+        let mut ring = self.ring.lock().unwrap();
+        if ring.closed {
+            drop(ring);
+            self.empty.signal();
+            return Err(PushError::Closed(value));
+        }
+        ring.push(value);
+        drop(ring);
+        self.full.signal();
+        Ok(())
+    }
+
+    // Called holding an item permit. Items are stored before their permit
+    // is released and close only adds permits afterwards, so finding the
+    // ring empty means the buffer was closed and drained.
+    fn take(&self) -> Option<T> {
+        let mut ring = self.ring.lock().unwrap();
+        match ring.pop() {
+            Some(value) => {
+                drop(ring);
+                self.empty.signal();
+                Some(value)
+            }
+            None => {
+                debug_assert!(ring.closed);
+                drop(ring);
+                self.full.signal();
+                None
+            }
+        }
+    }
+
+    fn full_or_closed(&self, value: T) -> PushError<T> {
+        if self.is_closed() {
+            PushError::Closed(value)
+        } else {
+            PushError::Full(value)
+        }
+    }
+
+    fn empty_or_closed(&self) -> PopError {
+        let ring = self.ring.lock().unwrap();
+        if ring.closed && ring.len == 0 {
+            PopError::Closed
+        } else {
+            PopError::Empty
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use crate::semaphore::FairSemaphore;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn keeps_fifo_order_across_wraparound() {
+        let buffer = BoundedBuffer::new(3);
+        for round in 0..4 {
+            buffer.push(round * 2).unwrap();
+            buffer.push(round * 2 + 1).unwrap();
+            assert_eq!(Some(round * 2), buffer.pop());
+            assert_eq!(Ok(round * 2 + 1), buffer.try_pop());
+        }
+        buffer.push(0).unwrap();
+        buffer.push(0).unwrap();
+        buffer.push(0).unwrap();
+        assert_eq!(Err(PushError::Full(7)), buffer.try_push(7));
+        assert_eq!(
+            Err(PushError::Full(8)),
+            buffer.push_timeout(8, Duration::from_millis(10))
+        );
+        assert_eq!(3, buffer.len());
+
+        while buffer.try_pop().is_ok() {}
+        assert_eq!(
+            Err(PopError::Empty),
+            buffer.pop_timeout(Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn close_drains_then_stops_everyone() {
+        let buffer = Arc::new(BoundedBuffer::<i32, FairSemaphore>::with_semaphores(2));
+        let consumers = (0..3)
+            .map(|_| {
+                let buffer = Arc::clone(&buffer);
+                thread::spawn(move || {
+                    let mut taken = vec![];
+                    while let Some(value) = buffer.pop() {
+                        taken.push(value);
+                    }
+                    taken
+                })
+            })
+            .collect::<Vec<_>>();
+        for value in 1..=10 {
+            buffer.push(value).unwrap();
+        }
+        buffer.close();
+        assert_eq!(Err(PushError::Closed(11)), buffer.push(11));
+
+        let mut taken = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect::<Vec<_>>();
+        taken.sort();
+        assert_eq!((1..=10).collect::<Vec<_>>(), taken);
+        assert_eq!(Err(PopError::Closed), buffer.try_pop());
+    }
+
+    #[test]
+    fn close_wakes_blocked_producers() {
+        let buffer = Arc::new(BoundedBuffer::new(1));
+        buffer.push(0).unwrap();
+        let producers = (1..=3)
+            .map(|value| {
+                let buffer = Arc::clone(&buffer);
+                thread::spawn(move || buffer.push(value))
+            })
+            .collect::<Vec<_>>();
+        buffer.close();
+        for producer in producers {
+            assert!(matches!(
+                producer.join().unwrap(),
+                Err(PushError::Closed(_))
+            ));
+        }
+        assert_eq!(Some(0), buffer.pop());
+        assert_eq!(None, buffer.pop());
+    }
+}
@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+// What a lock or semaphore went through since it was created: how many
+// times it was taken, how many rounds waiting threads went through after
+// their first (spin iterations for a spinlock, wakeups that found no
+// permit for a semaphore), how long they waited altogether and the most
+// threads that were waiting at once. Only counted when built with
+// `--features stats`, otherwise everything stays zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContentionStats {
+    pub acquisitions: u64,
+    pub spins: u64,
+    pub waited: Duration,
+    pub max_queue: usize,
+}
+
+impl ContentionStats {
+    // Two primitives seen as one, e.g. both semaphores of a buffer.
+    pub fn merge(self, other: Self) -> Self {
+        ContentionStats {
+            acquisitions: self.acquisitions + other.acquisitions,
+            spins: self.spins + other.spins,
+            waited: self.waited + other.waited,
+            max_queue: self.max_queue.max(other.max_queue),
+        }
+    }
+}
+
+pub(crate) use counters::{Contention, Waiter};
+
+// The counters are not part of any algorithm, so they stay std atomics
+// even in loom builds.
+#[cfg(feature = "stats")]
+mod counters {
+    use super::ContentionStats;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    #[derive(Debug, Default)]
+    pub(crate) struct Contention {
+        acquisitions: AtomicU64,
+        spins: AtomicU64,
+        waited_nanos: AtomicU64,
+        queue: AtomicUsize,
+        max_queue: AtomicUsize,
+    }
+
+    impl Contention {
+        pub(crate) fn waiter(&self) -> Waiter<'_> {
+            Waiter {
+                contention: self,
+                started: None,
+                spins: 0,
+            }
+        }
+
+        pub(crate) fn snapshot(&self) -> ContentionStats {
+            ContentionStats {
+                acquisitions: self.acquisitions.load(Ordering::Relaxed),
+                spins: self.spins.load(Ordering::Relaxed),
+                waited: Duration::from_nanos(self.waited_nanos.load(Ordering::Relaxed)),
+                max_queue: self.max_queue.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    // A thread on its way through a lock or semaphore. Whatever it waited
+    // is added up when it is dropped, whether it got through or gave up.
+    pub(crate) struct Waiter<'a> {
+        contention: &'a Contention,
+        started: Option<Instant>,
+        spins: u64,
+    }
+
+    impl Waiter<'_> {
+        // Another round of waiting. The first one puts the thread in the
+        // queue and starts the clock, the others count as spins.
+        pub(crate) fn spin(&mut self) {
+            if self.started.is_some() {
+                self.spins += 1;
+                return;
+            }
+            let queue = self.contention.queue.fetch_add(1, Ordering::Relaxed) + 1;
+            self.contention
+                .max_queue
+                .fetch_max(queue, Ordering::Relaxed);
+            self.started = Some(Instant::now());
+        }
+
+        pub(crate) fn acquired(self) {
+            self.contention.acquisitions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    impl Drop for Waiter<'_> {
+        fn drop(&mut self) {
+            let started = match self.started {
+                Some(started) => started,
+                None => return,
+            };
+            let waited = started.elapsed().as_nanos() as u64;
+            let contention = self.contention;
+            contention.waited_nanos.fetch_add(waited, Ordering::Relaxed);
+            contention.spins.fetch_add(self.spins, Ordering::Relaxed);
+            contention.queue.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Without the feature there is nothing to keep, and every call compiles
+// down to nothing.
+#[cfg(not(feature = "stats"))]
+mod counters {
+    use super::ContentionStats;
+    use std::marker::PhantomData;
+
+    #[derive(Debug, Default)]
+    pub(crate) struct Contention {}
+
+    impl Contention {
+        pub(crate) fn waiter(&self) -> Waiter<'_> {
+            Waiter(PhantomData)
+        }
+
+        pub(crate) fn snapshot(&self) -> ContentionStats {
+            ContentionStats::default()
+        }
+    }
+
+    pub(crate) struct Waiter<'a>(PhantomData<&'a Contention>);
+
+    impl Waiter<'_> {
+        pub(crate) fn spin(&mut self) {}
+
+        pub(crate) fn acquired(self) {}
+    }
+}
+
+#[cfg(all(test, feature = "stats"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waiters_are_counted_until_they_leave() {
+        let contention = Contention::default();
+        contention.waiter().acquired();
+
+        let mut first = contention.waiter();
+        first.spin();
+        first.spin();
+        let mut second = contention.waiter();
+        second.spin();
+        // Giving up still counts the time, just not as an acquisition.
+        drop(second);
+        first.acquired();
+
+        let mut third = contention.waiter();
+        third.spin();
+        third.acquired();
+
+        let stats = contention.snapshot();
+        assert_eq!(3, stats.acquisitions);
+        assert_eq!(1, stats.spins);
+        assert_eq!(2, stats.max_queue);
+    }
+}
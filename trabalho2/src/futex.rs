@@ -0,0 +1,310 @@
+use crate::contention::{Contention, ContentionStats};
+use crate::lock::{Lock, RawLock, RawTryLock};
+use crate::semaphore::RawSemaphore;
+use std::{
+    hint, ptr,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+// Attempts made spinning before going to sleep in the kernel.
+const SPIN_LIMIT: u32 = 100;
+
+// Sleeps while `futex` still holds `expected`, until woken up or the
+// timeout runs out. The kernel checks the value and queues us in one step,
+// so a wake-up sent after the value changed cannot be missed. Returning
+// says nothing about why we woke up; callers check the value again.
+fn futex_wait(futex: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+    let timespec = timeout.map(|timeout| libc::timespec {
+        tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    });
+    let timespec = timespec
+        .as_ref()
+        .map_or(ptr::null(), |timespec| timespec as *const libc::timespec);
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            futex.as_ptr(),
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected,
+            timespec,
+        );
+    }
+}
+
+// Wakes up to `count` threads sleeping on `futex`.
+fn futex_wake(futex: &AtomicU32, count: i32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            futex.as_ptr(),
+            libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+            count,
+        );
+    }
+}
+
+// A lock that spins for a moment and then sleeps in the kernel until the
+// holder wakes it up, the way glibc's mutex does. Sits between `Spinlock`,
+// which never sleeps, and the semaphores, which always go through a
+// Mutex and Condvar.
+pub type FutexLock<T> = Lock<RawFutexLock, T>;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+// Locked, and someone may be asleep waiting for it.
+const CONTENDED: u32 = 2;
+
+// The three-state lock from Drepper's "Futexes Are Tricky": only a release
+// from CONTENDED costs a system call, so the uncontended path stays in
+// user space.
+#[derive(Debug, Default)]
+pub struct RawFutexLock {
+    state: AtomicU32,
+    contention: Contention,
+}
+
+impl<T> FutexLock<T> {
+    pub fn stats(&self) -> ContentionStats {
+        self.raw().contention.snapshot()
+    }
+}
+
+unsafe impl RawLock for RawFutexLock {
+    type Token = ();
+
+    fn lock(&self) {
+        let mut waiter = self.contention.waiter();
+        if self.try_acquire() {
+            waiter.acquired();
+            return;
+        }
+        for _ in 0..SPIN_LIMIT {
+            waiter.spin();
+            hint::spin_loop();
+            if self.state.load(Ordering::Relaxed) == UNLOCKED && self.try_acquire() {
+                waiter.acquired();
+                return;
+            }
+        }
+        // From here on we take the lock as CONTENDED, since we cannot tell
+        // whether others are still asleep behind us.
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            waiter.spin();
+            futex_wait(&self.state, CONTENDED, None);
+        }
+        waiter.acquired();
+    }
+
+    unsafe fn unlock(&self, _: ()) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+
+    fn stats(&self) -> Option<ContentionStats> {
+        Some(self.contention.snapshot())
+    }
+}
+
+impl RawFutexLock {
+    fn try_acquire(&self) -> bool {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+unsafe impl RawTryLock for RawFutexLock {
+    fn try_lock(&self) -> Option<()> {
+        let acquired = self.try_acquire();
+        if acquired {
+            self.contention.waiter().acquired();
+        }
+        acquired.then_some(())
+    }
+}
+
+// A counting semaphore on a single futex word holding the free permits.
+// Waiters sleep while it is zero; `sleepers` lets `signal` skip the system
+// call when nobody is asleep.
+#[derive(Debug)]
+pub struct FutexSemaphore {
+    permits: AtomicU32,
+    sleepers: AtomicU32,
+    contention: Contention,
+}
+
+impl FutexSemaphore {
+    pub fn new(count: usize) -> Self {
+        FutexSemaphore {
+            permits: AtomicU32::new(count.try_into().expect("too many permits for a futex")),
+            sleepers: AtomicU32::new(0),
+            contention: Contention::default(),
+        }
+    }
+
+    pub fn available_threads(&self) -> usize {
+        self.permits.load(Ordering::Relaxed) as usize
+    }
+
+    pub fn wait(&self) {
+        self.take(None);
+    }
+
+    pub fn try_wait(&self) -> bool {
+        let taken = self.try_take();
+        if taken {
+            self.contention.waiter().acquired();
+        }
+        taken
+    }
+
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.take(Some(timeout))
+    }
+
+    pub fn signal(&self) {
+        self.permits.fetch_add(1, Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            futex_wake(&self.permits, 1);
+        }
+    }
+
+    pub fn stats(&self) -> ContentionStats {
+        self.contention.snapshot()
+    }
+
+    fn try_take(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => permits = current,
+            }
+        }
+        false
+    }
+
+    // Waits for a permit, for at most `timeout` if there is one.
+    fn take(&self, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut waiter = self.contention.waiter();
+        for _ in 0..SPIN_LIMIT {
+            if self.try_take() {
+                waiter.acquired();
+                return true;
+            }
+            waiter.spin();
+            hint::spin_loop();
+        }
+        loop {
+            if self.try_take() {
+                waiter.acquired();
+                return true;
+            }
+            let left = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if !left.is_zero() => Some(left),
+                    _ => return false,
+                },
+                None => None,
+            };
+            waiter.spin();
+            // Pairs with `signal`: either it sees us in `sleepers` and
+            // wakes us up, or the kernel sees its permit and does not let
+            // us sleep.
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+            futex_wait(&self.permits, 0, left);
+            self.sleepers.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// The trait is how generic code reaches the methods above.
+impl RawSemaphore for FutexSemaphore {
+    fn new(count: usize) -> Self {
+        FutexSemaphore::new(count)
+    }
+
+    fn available_threads(&self) -> usize {
+        FutexSemaphore::available_threads(self)
+    }
+
+    fn wait(&self) {
+        FutexSemaphore::wait(self)
+    }
+
+    fn try_wait(&self) -> bool {
+        FutexSemaphore::try_wait(self)
+    }
+
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        FutexSemaphore::wait_timeout(self, timeout)
+    }
+
+    fn signal(&self) {
+        FutexSemaphore::signal(self)
+    }
+
+    fn stats(&self) -> ContentionStats {
+        FutexSemaphore::stats(self)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    #[test]
+    fn lock_keeps_threads_out_of_each_other() {
+        let lock = FutexLock::new(0);
+        let inside = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1_000 {
+                        let mut count = lock.lock();
+                        assert_eq!(0, inside.fetch_add(1, Ordering::SeqCst));
+                        // Hold on long enough for the others to go to sleep.
+                        if *count % 100 == 0 {
+                            thread::yield_now();
+                        }
+                        *count += 1;
+                        inside.fetch_sub(1, Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+        assert_eq!(4_000, *lock.lock());
+        let _held = lock.lock();
+        assert!(lock.try_lock().is_none());
+    }
+
+    #[test]
+    fn semaphore_wakes_sleeping_waiters() {
+        let semaphore = FutexSemaphore::new(0);
+        thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| semaphore.wait());
+            }
+            thread::sleep(Duration::from_millis(10));
+            for _ in 0..3 {
+                semaphore.signal();
+            }
+        });
+        assert_eq!(0, semaphore.available_threads());
+        assert!(!semaphore.try_wait());
+        assert!(!semaphore.wait_timeout(Duration::from_millis(10)));
+        semaphore.signal();
+        assert!(semaphore.wait_timeout(Duration::from_millis(10)));
+    }
+}
@@ -1,2 +1,14 @@
+pub mod bench;
+pub mod bounded_buffer;
+pub mod clh;
+pub mod contention;
+#[cfg(target_os = "linux")]
+pub mod futex;
+pub mod lock;
+pub mod mcs;
+pub mod mpmc;
+pub mod report;
 pub mod semaphore;
 pub mod spinlock;
+mod sync;
+pub mod ticket;
@@ -0,0 +1,146 @@
+use crate::contention::ContentionStats;
+use std::{
+    cell::UnsafeCell,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+
+// The locking algorithm on its own, without any data attached to it.
+// `lock` hands back a token that must be given to `unlock` when the
+// critical section ends; queue locks use it to remember their queue node.
+//
+// Implementations must only let one thread between `lock` (or a
+// successful `try_lock`) and the matching `unlock`, with Acquire/Release
+// ordering so the protected data is visible to the next holder.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe trait RawLock: Default + Send + Sync {
+    type Token;
+
+    fn lock(&self) -> Self::Token;
+
+    // `token` must come from `lock` or `try_lock` on this same lock.
+    unsafe fn unlock(&self, token: Self::Token);
+
+    // Contention counters, for the locks that keep them.
+    fn stats(&self) -> Option<ContentionStats> {
+        None
+    }
+}
+
+// Locks that can give up straight away instead of waiting. CLH does not
+// implement it: peeking at the tail node is not safe while another thread
+// may be freeing it. A successful `try_lock` must give the same guarantees
+// as `lock`.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe trait RawTryLock: RawLock {
+    fn try_lock(&self) -> Option<Self::Token>;
+}
+
+// A value of type T protected by the locking algorithm L.
+// The value can only be reached through the guard returned by `lock`
+// or `try_lock`, and the lock is released when that guard is dropped.
+pub struct Lock<L: RawLock, T> {
+    raw: L,
+    data: UnsafeCell<T>,
+}
+
+// Holding the guard means we hold the lock, so it can hand out
+// references to the protected value. Sharing the guard between threads
+// shares the value too, so it is only Sync when T is; the doctest keeps
+// it that way.
+/// ```compile_fail
+/// use sd::spinlock::Spinlock;
+/// use std::cell::Cell;
+///
+/// fn share<T: Sync>(_: &T) {}
+///
+/// let lock = Spinlock::new(Cell::new(0));
+/// share(&lock.lock());
+/// ```
+pub struct LockGuard<'a, L: RawLock, T> {
+    lock: &'a Lock<L, T>,
+    token: ManuallyDrop<L::Token>,
+}
+
+impl<L: RawLock, T> Lock<L, T> {
+    pub fn new(initial_value: T) -> Self {
+        Self::with_raw(L::default(), initial_value)
+    }
+
+    pub fn with_raw(raw: L, initial_value: T) -> Self {
+        Self {
+            raw,
+            data: UnsafeCell::new(initial_value),
+        }
+    }
+
+    pub fn raw(&self) -> &L {
+        &self.raw
+    }
+
+    pub fn lock(&self) -> LockGuard<'_, L, T> {
+        let token = self.raw.lock();
+        LockGuard {
+            lock: self,
+            token: ManuallyDrop::new(token),
+        }
+    }
+
+    // No locking is needed when we have the only reference to the lock.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<L: RawTryLock, T> Lock<L, T> {
+    pub fn try_lock(&self) -> Option<LockGuard<'_, L, T>> {
+        self.raw.try_lock().map(|token| LockGuard {
+            lock: self,
+            token: ManuallyDrop::new(token),
+        })
+    }
+}
+
+impl<L: RawLock, T: Default> Default for Lock<L, T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+// The lock hands out &mut T to one thread at a time, so sharing it is
+// fine as long as T itself can be sent to another thread.
+unsafe impl<L: RawLock, T: Send> Sync for Lock<L, T> {}
+unsafe impl<L: RawLock, T: Send> Send for Lock<L, T> {}
+
+// Without this the guard would be Sync whenever the lock is, letting
+// threads reach a T that is Send but not Sync through a shared guard.
+// Same bound as std's MutexGuard.
+unsafe impl<L: RawLock, T: Sync> Sync for LockGuard<'_, L, T> {}
+
+impl<L: RawLock, T> Deref for LockGuard<'_, L, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<L: RawLock, T> DerefMut for LockGuard<'_, L, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<L: RawLock, T> Drop for LockGuard<'_, L, T> {
+    fn drop(&mut self) {
+        // The token is never touched again after this.
+        unsafe {
+            let token = ManuallyDrop::take(&mut self.token);
+            self.lock.raw.unlock(token);
+        }
+    }
+}
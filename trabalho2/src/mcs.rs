@@ -0,0 +1,211 @@
+use crate::lock::{Lock, RawLock, RawTryLock};
+use crate::sync::{
+    atomic::{AtomicBool, AtomicPtr, Ordering},
+    hint,
+};
+use std::ptr::{self, NonNull};
+
+// Mellor-Crummey and Scott queue lock. Waiters form a linked list and each
+// one spins on a flag in its own node, so a release only touches the cache
+// line of the next thread in line. The lock is handed over in arrival order.
+pub type McsLock<T> = Lock<RawMcsLock, T>;
+
+#[derive(Debug, Default)]
+pub struct RawMcsLock {
+    // Last node in the queue, null when the lock is free.
+    tail: AtomicPtr<Node>,
+}
+
+#[derive(Debug)]
+struct Node {
+    next: AtomicPtr<Node>,
+    locked: AtomicBool,
+}
+
+// The queue node owned by the current holder. It is freed on unlock, once
+// the successor (if any) no longer needs it.
+pub struct McsToken(NonNull<Node>);
+
+fn new_node() -> NonNull<Node> {
+    let node = Box::new(Node {
+        next: AtomicPtr::new(ptr::null_mut()),
+        locked: AtomicBool::new(true),
+    });
+    NonNull::from(Box::leak(node))
+}
+
+unsafe impl RawLock for RawMcsLock {
+    type Token = McsToken;
+
+    fn lock(&self) -> McsToken {
+        let node = new_node();
+        let prev = self.tail.swap(node.as_ptr(), Ordering::AcqRel);
+        if !prev.is_null() {
+            // The predecessor keeps its node alive until it has seen us.
+            unsafe {
+                (*prev).next.store(node.as_ptr(), Ordering::Release);
+                while node.as_ref().locked.load(Ordering::Acquire) {
+                    hint::spin_loop();
+                }
+            }
+        }
+        McsToken(node)
+    }
+
+    unsafe fn unlock(&self, token: McsToken) {
+        let node = token.0.as_ptr();
+        let mut next = (*node).next.load(Ordering::Acquire);
+        if next.is_null() {
+            // Nobody queued behind us: leave the lock free.
+            if self
+                .tail
+                .compare_exchange(node, ptr::null_mut(), Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                drop(Box::from_raw(node));
+                return;
+            }
+            // Someone swapped the tail but has not linked itself yet.
+            loop {
+                next = (*node).next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                hint::spin_loop();
+            }
+        }
+        (*next).locked.store(false, Ordering::Release);
+        drop(Box::from_raw(node));
+    }
+}
+
+unsafe impl RawTryLock for RawMcsLock {
+    // Only succeeds when the queue is empty. Like the swap in `lock`, this
+    // publishes our node to the next thread that queues behind us.
+    fn try_lock(&self) -> Option<McsToken> {
+        let node = new_node();
+        match self.tail.compare_exchange(
+            ptr::null_mut(),
+            node.as_ptr(),
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => Some(McsToken(node)),
+            Err(_) => {
+                drop(unsafe { Box::from_raw(node.as_ptr()) });
+                None
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{atomic::AtomicUsize, Arc},
+        thread,
+    };
+
+    #[test]
+    fn keeps_threads_out_of_each_other() {
+        let lock = Arc::new(McsLock::new(0));
+        let inside = Arc::new(AtomicUsize::new(0));
+        let handles = (0..4)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                let inside = Arc::clone(&inside);
+                thread::spawn(move || {
+                    for _ in 0..1_000 {
+                        let mut count = lock.lock();
+                        assert_eq!(0, inside.fetch_add(1, Ordering::SeqCst));
+                        *count += 1;
+                        inside.fetch_sub(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(4_000, *lock.lock());
+    }
+
+    #[test]
+    fn try_lock_only_succeeds_when_free() {
+        let lock = McsLock::new(0);
+        let held = lock.lock();
+        assert!(lock.try_lock().is_none());
+        drop(held);
+        *lock.try_lock().unwrap() += 1;
+        assert_eq!(1, *lock.lock());
+        assert!(lock.raw().tail.load(Ordering::Relaxed).is_null());
+    }
+
+    #[test]
+    fn hands_the_lock_over_in_arrival_order() {
+        let lock = McsLock::new(Vec::new());
+        let held = lock.lock();
+        thread::scope(|scope| {
+            for id in 0..3 {
+                let lock = &lock;
+                let tail = lock.raw().tail.load(Ordering::Relaxed);
+                scope.spawn(move || lock.lock().push(id));
+                // Let the thread queue up before the next one arrives.
+                while lock.raw().tail.load(Ordering::Relaxed) == tail {
+                    thread::yield_now();
+                }
+            }
+            drop(held);
+        });
+        assert_eq!([0, 1, 2], lock.lock()[..]);
+    }
+}
+
+// Run with RUSTFLAGS="--cfg loom" cargo test --release.
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use crate::sync::{atomic::AtomicUsize, thread};
+    use loom::sync::Arc;
+
+    #[test]
+    fn contended_lock_is_exclusive() {
+        loom::model(|| {
+            let lock = Arc::new(McsLock::new(0));
+            let inside = Arc::new(AtomicUsize::new(0));
+            let increment = {
+                let lock = Arc::clone(&lock);
+                let inside = Arc::clone(&inside);
+                move || {
+                    let mut count = lock.lock();
+                    assert_eq!(0, inside.fetch_add(1, Ordering::SeqCst));
+                    *count += 1;
+                    inside.fetch_sub(1, Ordering::SeqCst);
+                }
+            };
+            let other = thread::spawn(increment.clone());
+            increment();
+            other.join().unwrap();
+            assert_eq!(2, *lock.lock());
+        });
+    }
+
+    // A failed `try_lock` frees its node while the other thread queues.
+    #[test]
+    fn try_lock_races_with_lock() {
+        loom::model(|| {
+            let lock = Arc::new(McsLock::new(0));
+            let other = {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || *lock.lock() += 1)
+            };
+            if let Some(mut count) = lock.try_lock() {
+                *count += 1;
+            }
+            other.join().unwrap();
+            let count = *lock.lock();
+            assert!(count == 1 || count == 2);
+        });
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    cell::UnsafeCell,
+    hint,
+    mem::MaybeUninit,
+    ops::Deref,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// A bounded multi-producer multi-consumer queue that never blocks, after
+// Dmitry Vyukov's array queue. Every slot carries a stamp telling whether
+// it is ready to be written or read in the current lap around the array,
+// so producers and consumers only contend on the position they claim with
+// a compare-and-swap, never on a lock.
+//
+// `head` and `tail` hold a lap number in the bits above `one_lap` and a
+// slot index below it. Keeping laps apart this way lets a full slot be told
+// from an empty one even when the capacity is 1.
+pub struct MpmcQueue<T> {
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+    slots: Box<[Slot<T>]>,
+    one_lap: usize,
+}
+
+struct Slot<T> {
+    // `tail` when the slot can be written in this lap, `head + 1` when it
+    // holds a value to read.
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Keeps `head` and `tail` on separate cache lines so producers and
+// consumers do not slow each other down.
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> MpmcQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a bounded queue needs at least one slot");
+        MpmcQueue {
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+            slots: (0..capacity)
+                .map(|i| Slot {
+                    stamp: AtomicUsize::new(i),
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                })
+                .collect(),
+            one_lap: (capacity + 1).next_power_of_two(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    // Hands the value back if the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[tail & (self.one_lap - 1)];
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            if stamp == tail {
+                match self.tail.compare_exchange_weak(
+                    tail,
+                    self.next(tail),
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.stamp.store(tail.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => tail = current,
+                }
+            } else if stamp.wrapping_add(self.one_lap) == tail.wrapping_add(1) {
+                // The slot still holds last lap's value. The queue is full
+                // unless a consumer moved `head` since.
+                if self.head.load(Ordering::SeqCst).wrapping_add(self.one_lap) == tail {
+                    return Err(value);
+                }
+                hint::spin_loop();
+                tail = self.tail.load(Ordering::Relaxed);
+            } else {
+                // Another producer claimed the slot and is still writing it.
+                hint::spin_loop();
+                tail = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Returns None if the queue is empty.
+    pub fn pop(&self) -> Option<T> {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[head & (self.one_lap - 1)];
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            if stamp == head.wrapping_add(1) {
+                match self.head.compare_exchange_weak(
+                    head,
+                    self.next(head),
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        slot.stamp
+                            .store(head.wrapping_add(self.one_lap), Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => head = current,
+                }
+            } else if stamp == head {
+                // Nothing written to this slot yet in this lap.
+                if self.tail.load(Ordering::SeqCst) == head {
+                    return None;
+                }
+                hint::spin_loop();
+                head = self.head.load(Ordering::Relaxed);
+            } else {
+                hint::spin_loop();
+                head = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Position after `position`, moving on to the next lap after the last slot.
+    fn next(&self, position: usize) -> usize {
+        let index = position & (self.one_lap - 1);
+        let lap = position & !(self.one_lap - 1);
+        if index + 1 < self.slots.len() {
+            position + 1
+        } else {
+            lap.wrapping_add(self.one_lap)
+        }
+    }
+}
+
+// Values only move between threads through the queue, so T: Send is enough.
+unsafe impl<T: Send> Sync for MpmcQueue<T> {}
+unsafe impl<T: Send> Send for MpmcQueue<T> {}
+
+impl<T> Drop for MpmcQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn full_and_empty_with_a_single_slot() {
+        let queue = MpmcQueue::new(1);
+        assert_eq!(None, queue.pop());
+        for value in 0..3 {
+            queue.push(value).unwrap();
+            assert_eq!(Err(42), queue.push(42));
+            assert_eq!(Some(value), queue.pop());
+            assert_eq!(None, queue.pop());
+        }
+    }
+
+    #[test]
+    fn every_value_comes_out_once() {
+        let queue = Arc::new(MpmcQueue::new(4));
+        let producers = (0..4)
+            .map(|p| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    for i in 0..1_000 {
+                        let mut value = p * 1_000 + i;
+                        while let Err(back) = queue.push(value) {
+                            value = back;
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let consumers = (0..4)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    let mut taken = vec![];
+                    while taken.len() < 1_000 {
+                        match queue.pop() {
+                            Some(value) => taken.push(value),
+                            None => thread::yield_now(),
+                        }
+                    }
+                    taken
+                })
+            })
+            .collect::<Vec<_>>();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut taken = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect::<Vec<_>>();
+        taken.sort();
+        assert_eq!((0..4_000).collect::<Vec<_>>(), taken);
+    }
+
+    #[test]
+    fn drops_values_left_behind() {
+        let value = Arc::new(());
+        let queue = MpmcQueue::new(3);
+        queue.push(Arc::clone(&value)).unwrap();
+        queue.push(Arc::clone(&value)).unwrap();
+        drop(queue);
+        assert_eq!(1, Arc::strong_count(&value));
+    }
+}
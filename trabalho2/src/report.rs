@@ -0,0 +1,578 @@
+use crate::bench::{BenchConfig, Stats, CONTENTION_COLUMNS};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+// Layouts of the files written before the benchmarks had headers. Files
+// without a header are read with the semaphore one if their name says so
+// and the spinlock one otherwise, unless `--columns` gives the layout.
+const SEMAPHORE_COLUMNS: [&str; 5] = ["n", "n_p", "n_c", "time", "semaphore"];
+const SPINLOCK_COLUMNS: [&str; 6] = ["k", "n", "sum", "time", "lock", "max_wait"];
+
+// Columns of a samples file that were measured rather than set up.
+const MEASURED_COLUMNS: [&str; 3] = ["time", "sum", "max_wait"];
+
+// Columns of a summary file written by `Bench`.
+const SUMMARY_COLUMNS: [&str; 10] = [
+    "samples",
+    "mean",
+    "median",
+    "stddev",
+    "min",
+    "max",
+    "p90",
+    "p99",
+    "ci95_low",
+    "ci95_high",
+];
+
+// Labels counting threads, the x axis of every chart, and labels telling
+// the compared implementations apart, one line each. Any other label, such
+// as the buffer size, gets its own chart.
+const THREAD_LABELS: [&str; 3] = ["k", "n_p", "n_c"];
+const SERIES_LABELS: [&str; 2] = ["lock", "semaphore"];
+
+const COLORS: [&str; 10] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+    "#bcbd22", "#17becf",
+];
+
+// `report FILE...` reads benchmark results and writes one SVG per chart
+// and an index.html showing them all to the `output` directory (default
+// ./data/report). Settings besides the common ones:
+//   columns  layout of files without a header, e.g. k,n,read%,time,lock
+pub fn run(files: &[String], config: &BenchConfig) -> Result<(), String> {
+    config.check_keys(&["columns"])?;
+    if files.is_empty() {
+        return Err("report needs at least one results file".to_string());
+    }
+    let columns = config.list::<String>("columns", &[])?;
+    let output = Path::new(config.get("output").unwrap_or("./data/report"));
+    fs::create_dir_all(output).map_err(|e| format!("creating {}: {}", output.display(), e))?;
+
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Benchmark report</title>\n<style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; margin-bottom: 2em; }\n\
+         td, th { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: right; }\n\
+         </style>\n</head>\n<body>\n<h1>Benchmark report</h1>\n\
+         <p>Times in milliseconds, mean &plusmn; 95% confidence interval.</p>\n",
+    );
+    for file in files {
+        let contents = fs::read_to_string(file).map_err(|e| format!("reading {}: {}", file, e))?;
+        let table =
+            Table::parse(&contents, &columns, file).map_err(|e| format!("{}: {}", file, e))?;
+        let stem = Path::new(file)
+            .file_stem()
+            .map_or("results".into(), |stem| stem.to_string_lossy());
+
+        writeln!(html, "<h2>{}</h2>", escape(file)).unwrap();
+        if !table.metadata.is_empty() {
+            let metadata = table
+                .metadata
+                .iter()
+                .map(|(key, value)| format!("{}: {}", escape(key), escape(value)))
+                .collect::<Vec<_>>();
+            writeln!(html, "<p>{}</p>", metadata.join(" &middot; ")).unwrap();
+        }
+        let charts = charts(&table.points()?);
+        for (i, chart) in charts.iter().enumerate() {
+            let svg = chart.svg();
+            let path = output.join(format!("{}-{}.svg", stem, i + 1));
+            fs::write(&path, &svg).map_err(|e| format!("writing {}: {}", path.display(), e))?;
+            writeln!(html, "<h3>{}</h3>\n{}", escape(&chart.title), svg).unwrap();
+            html.push_str(&chart.html_table());
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    let index = output.join("index.html");
+    fs::write(&index, html).map_err(|e| format!("writing {}: {}", index.display(), e))
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Table {
+    // `# key: value` lines written by `Bench`.
+    metadata: Vec<(String, String)>,
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+// Column names and values identifying a case.
+type Labels = Vec<(String, String)>;
+
+// Mean time of a case with the bounds of its error bar.
+#[derive(Debug, Clone, PartialEq)]
+struct Point {
+    labels: Labels,
+    mean: f64,
+    low: f64,
+    high: f64,
+}
+
+impl Table {
+    fn parse(contents: &str, columns: &[String], name: &str) -> Result<Table, String> {
+        let mut table = Table::default();
+        for line in contents.lines().map(str::trim) {
+            if let Some(comment) = line.strip_prefix('#') {
+                if let Some((key, value)) = comment.split_once(':') {
+                    table
+                        .metadata
+                        .push((key.trim().to_string(), value.trim().to_string()));
+                }
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let fields = line
+                .split(',')
+                .map(|field| field.trim().to_string())
+                .collect::<Vec<_>>();
+            if table.header.is_empty() {
+                if fields[0].parse::<f64>().is_err() {
+                    table.header = fields;
+                    continue;
+                }
+                table.header = legacy_header(columns, name, fields.len())?;
+            }
+            if fields.len() != table.header.len() {
+                return Err(format!(
+                    "expected {} columns, found {}: {}",
+                    table.header.len(),
+                    fields.len(),
+                    line
+                ));
+            }
+            table.rows.push(fields);
+        }
+        Ok(table)
+    }
+
+    fn column(&self, name: &str) -> Option<usize> {
+        self.header.iter().position(|column| column == name)
+    }
+
+    fn number(&self, row: &[String], column: usize) -> Result<f64, String> {
+        row[column]
+            .parse()
+            .map_err(|_| format!("{}: not a number: {}", self.header[column], row[column]))
+    }
+
+    // Summaries are taken as they are, samples are grouped by case first.
+    // Contention counters are measured too, so they never tell cases apart.
+    fn points(&self) -> Result<Vec<Point>, String> {
+        let labels = |skipped: &[&str], row: &[String]| -> Labels {
+            self.header
+                .iter()
+                .zip(row)
+                .filter(|(column, _)| {
+                    !skipped.contains(&column.as_str())
+                        && !CONTENTION_COLUMNS.contains(&column.as_str())
+                })
+                .map(|(column, value)| (column.clone(), value.clone()))
+                .collect()
+        };
+
+        if let Some(mean) = self.column("mean") {
+            let bounds = self.column("ci95_low").zip(self.column("ci95_high"));
+            return self
+                .rows
+                .iter()
+                .map(|row| {
+                    let value = self.number(row, mean)?;
+                    let (low, high) = match bounds {
+                        Some((low, high)) => (self.number(row, low)?, self.number(row, high)?),
+                        None => (value, value),
+                    };
+                    Ok(Point {
+                        labels: labels(&SUMMARY_COLUMNS, row),
+                        mean: value,
+                        low,
+                        high,
+                    })
+                })
+                .collect();
+        }
+
+        let time = self
+            .column("time")
+            .ok_or("neither a time nor a mean column")?;
+        let mut cases: Vec<(Labels, Vec<f64>)> = vec![];
+        for row in &self.rows {
+            let case = labels(&MEASURED_COLUMNS, row);
+            let time = self.number(row, time)?;
+            match cases.iter_mut().find(|(labels, _)| *labels == case) {
+                Some((_, times)) => times.push(time),
+                None => cases.push((case, vec![time])),
+            }
+        }
+        Ok(cases
+            .into_iter()
+            .map(|(labels, times)| {
+                let stats = Stats::from_samples(&times).unwrap();
+                Point {
+                    labels,
+                    mean: stats.mean,
+                    low: stats.ci95.0,
+                    high: stats.ci95.1,
+                }
+            })
+            .collect())
+    }
+}
+
+fn legacy_header(columns: &[String], name: &str, len: usize) -> Result<Vec<String>, String> {
+    let layout = if !columns.is_empty() {
+        columns.to_vec()
+    } else if name.contains("semaphore") {
+        SEMAPHORE_COLUMNS.map(String::from).to_vec()
+    } else {
+        SPINLOCK_COLUMNS.map(String::from).to_vec()
+    };
+    if len > layout.len() {
+        return Err(format!(
+            "no header and more than the {} columns of {}, give them with --columns",
+            layout.len(),
+            layout.join(",")
+        ));
+    }
+    Ok(layout[..len].to_vec())
+}
+
+#[derive(Debug, PartialEq)]
+struct Chart {
+    title: String,
+    x_label: String,
+    categories: Vec<String>,
+    series: Vec<Series>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Series {
+    name: String,
+    // Mean, low and high for every category, if measured.
+    points: Vec<Option<(f64, f64, f64)>>,
+}
+
+// Splits the points into charts of time against thread count.
+fn charts(points: &[Point]) -> Vec<Chart> {
+    let pick = |point: &Point, keys: &[&str], separator: &str| {
+        point
+            .labels
+            .iter()
+            .filter(|(key, _)| keys.contains(&key.as_str()))
+            .map(|(_, value)| value.as_str())
+            .collect::<Vec<_>>()
+            .join(separator)
+    };
+    let mut charts: Vec<Chart> = vec![];
+    for point in points {
+        let title = point
+            .labels
+            .iter()
+            .filter(|(key, _)| {
+                !THREAD_LABELS.contains(&key.as_str()) && !SERIES_LABELS.contains(&key.as_str())
+            })
+            .map(|(key, value)| format!("{} = {}", key, value))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let chart = match charts.iter().position(|chart| chart.title == title) {
+            Some(i) => &mut charts[i],
+            None => {
+                let x_label = point
+                    .labels
+                    .iter()
+                    .filter(|(key, _)| THREAD_LABELS.contains(&key.as_str()))
+                    .map(|(key, _)| key.as_str())
+                    .collect::<Vec<_>>()
+                    .join(":");
+                charts.push(Chart {
+                    title,
+                    x_label,
+                    categories: vec![],
+                    series: vec![],
+                });
+                charts.last_mut().unwrap()
+            }
+        };
+
+        let x = pick(point, &THREAD_LABELS, ":");
+        let category = match chart.categories.iter().position(|c| *c == x) {
+            Some(i) => i,
+            None => {
+                chart.categories.push(x);
+                chart
+                    .series
+                    .iter_mut()
+                    .for_each(|series| series.points.push(None));
+                chart.categories.len() - 1
+            }
+        };
+        let name = pick(point, &SERIES_LABELS, " ");
+        let series = match chart.series.iter().position(|s| s.name == name) {
+            Some(i) => &mut chart.series[i],
+            None => {
+                chart.series.push(Series {
+                    name,
+                    points: vec![None; chart.categories.len()],
+                });
+                chart.series.last_mut().unwrap()
+            }
+        };
+        series.points[category] = Some((point.mean, point.low, point.high));
+    }
+    charts
+}
+
+const WIDTH: f64 = 720.0;
+const HEIGHT: f64 = 420.0;
+const LEFT: f64 = 70.0;
+const RIGHT: f64 = 150.0;
+const TOP: f64 = 20.0;
+const BOTTOM: f64 = 50.0;
+
+impl Chart {
+    fn svg(&self) -> String {
+        let plot_width = WIDTH - LEFT - RIGHT;
+        let plot_height = HEIGHT - TOP - BOTTOM;
+        let top = self
+            .series
+            .iter()
+            .flat_map(|series| series.points.iter().flatten())
+            .map(|&(mean, _, high)| mean.max(high))
+            .fold(0.0, f64::max);
+        let step = tick_step(top);
+        let y_max = (top / step).ceil().max(1.0) * step;
+        let y = |value: f64| TOP + plot_height * (1.0 - value.max(0.0) / y_max);
+        let slot = plot_width / self.categories.len().max(1) as f64;
+        let x = |category: usize| LEFT + slot * (category as f64 + 0.5);
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{WIDTH}\" height=\"{HEIGHT}\" \
+             viewBox=\"0 0 {WIDTH} {HEIGHT}\" font-family=\"sans-serif\" font-size=\"12\">"
+        )
+        .unwrap();
+        writeln!(svg, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>").unwrap();
+
+        // Axes, with grid lines at every tick of the time axis.
+        let mut tick = 0.0;
+        while tick <= y_max + step / 2.0 {
+            writeln!(
+                svg,
+                "<line x1=\"{LEFT}\" y1=\"{0:.1}\" x2=\"{1:.1}\" y2=\"{0:.1}\" stroke=\"#eee\"/>\
+                 <text x=\"{2:.1}\" y=\"{0:.1}\" text-anchor=\"end\" dy=\"4\">{3}</text>",
+                y(tick),
+                LEFT + plot_width,
+                LEFT - 6.0,
+                format_tick(tick, step)
+            )
+            .unwrap();
+            tick += step;
+        }
+        writeln!(
+            svg,
+            "<path d=\"M{LEFT},{TOP} V{0} H{1}\" fill=\"none\" stroke=\"black\"/>",
+            TOP + plot_height,
+            LEFT + plot_width
+        )
+        .unwrap();
+        for (i, category) in self.categories.iter().enumerate() {
+            writeln!(
+                svg,
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+                x(i),
+                TOP + plot_height + 18.0,
+                escape(category)
+            )
+            .unwrap();
+        }
+        writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+            LEFT + plot_width / 2.0,
+            HEIGHT - 8.0,
+            escape(if self.x_label.is_empty() {
+                "case"
+            } else {
+                &self.x_label
+            })
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            "<text transform=\"translate(16 {:.1}) rotate(-90)\" text-anchor=\"middle\">\
+             time (ms)</text>",
+            TOP + plot_height / 2.0
+        )
+        .unwrap();
+
+        // One line per series, with an error bar on every point.
+        for (i, series) in self.series.iter().enumerate() {
+            let color = COLORS[i % COLORS.len()];
+            let line = series
+                .points
+                .iter()
+                .enumerate()
+                .filter_map(|(c, point)| {
+                    point.map(|(mean, _, _)| format!("{:.1},{:.1}", x(c), y(mean)))
+                })
+                .collect::<Vec<_>>();
+            writeln!(
+                svg,
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{color}\" stroke-width=\"2\"/>",
+                line.join(" ")
+            )
+            .unwrap();
+            for (c, point) in series.points.iter().enumerate() {
+                if let Some((mean, low, high)) = *point {
+                    writeln!(
+                        svg,
+                        "<path class=\"error-bar\" d=\"M{0:.1},{1:.1} V{2:.1} M{3:.1},{1:.1} H{4:.1} \
+                         M{3:.1},{2:.1} H{4:.1}\" stroke=\"{color}\"/>\
+                         <circle cx=\"{0:.1}\" cy=\"{5:.1}\" r=\"3\" fill=\"{color}\"/>",
+                        x(c),
+                        y(low),
+                        y(high),
+                        x(c) - 4.0,
+                        x(c) + 4.0,
+                        y(mean)
+                    )
+                    .unwrap();
+                }
+            }
+            let legend_y = TOP + 10.0 + 18.0 * i as f64;
+            writeln!(
+                svg,
+                "<rect x=\"{0:.1}\" y=\"{1:.1}\" width=\"12\" height=\"12\" fill=\"{color}\"/>\
+                 <text x=\"{2:.1}\" y=\"{3:.1}\">{4}</text>",
+                WIDTH - RIGHT + 16.0,
+                legend_y - 10.0,
+                WIDTH - RIGHT + 34.0,
+                legend_y,
+                escape(if series.name.is_empty() {
+                    "time"
+                } else {
+                    &series.name
+                })
+            )
+            .unwrap();
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    fn html_table(&self) -> String {
+        let mut html = String::from("<table>\n<tr><th>");
+        html.push_str(&escape(&self.x_label));
+        html.push_str("</th>");
+        for series in &self.series {
+            write!(html, "<th>{}</th>", escape(&series.name)).unwrap();
+        }
+        html.push_str("</tr>\n");
+        for (c, category) in self.categories.iter().enumerate() {
+            write!(html, "<tr><td>{}</td>", escape(category)).unwrap();
+            for series in &self.series {
+                match series.points[c] {
+                    Some((mean, low, high)) => write!(
+                        html,
+                        "<td>{:.3} &plusmn; {:.3}</td>",
+                        mean,
+                        (high - low) / 2.0
+                    )
+                    .unwrap(),
+                    None => html.push_str("<td></td>"),
+                }
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</table>\n");
+        html
+    }
+}
+
+// Distance between ticks giving about five of them up to `max`, rounded
+// to 1, 2 or 5 times a power of ten.
+fn tick_step(max: f64) -> f64 {
+    if max <= 0.0 || !max.is_finite() {
+        return 1.0;
+    }
+    let raw = max / 5.0;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    match raw / magnitude {
+        r if r <= 1.0 => magnitude,
+        r if r <= 2.0 => 2.0 * magnitude,
+        r if r <= 5.0 => 5.0 * magnitude,
+        _ => 10.0 * magnitude,
+    }
+}
+
+fn format_tick(value: f64, step: f64) -> String {
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    format!("{:.*}", decimals, value)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_legacy_samples_and_summaries() {
+        let legacy = "1,1,1,10,std\n1,1,1,14,std\n1,1,2,8,std\n1,1,1,30,fair\n";
+        let table = Table::parse(legacy, &[], "data/semaphore_results.csv").unwrap();
+        assert_eq!(SEMAPHORE_COLUMNS.map(String::from).to_vec(), table.header);
+        let points = table.points().unwrap();
+        assert_eq!(3, points.len());
+        assert_eq!(12.0, points[0].mean);
+        assert!(points[0].low < 12.0 && points[0].high > 12.0);
+        assert_eq!(("n_c".to_string(), "2".to_string()), points[1].labels[2]);
+
+        let summary = "# benchmark: spinlock\n\
+                       k,n,lock,samples,mean,median,stddev,min,max,p90,p99,ci95_low,ci95_high\n\
+                       4,100,mcs,3,2.0,2.0,0.1,1.9,2.1,2.1,2.1,1.8,2.2\n";
+        let table = Table::parse(summary, &[], "out.csv").unwrap();
+        assert_eq!(
+            vec![("benchmark".to_string(), "spinlock".to_string())],
+            table.metadata
+        );
+        let points = table.points().unwrap();
+        assert_eq!(3, points[0].labels.len());
+        assert_eq!(
+            (2.0, 1.8, 2.2),
+            (points[0].mean, points[0].low, points[0].high)
+        );
+
+        assert!(Table::parse("1,2,3,4,5,6,7\n", &[], "spinlock.csv").is_err());
+    }
+
+    #[test]
+    fn one_chart_per_buffer_size() {
+        let samples = "n,n_p,n_c,semaphore,time\n\
+                       4,1,1,std,1.0\n4,1,2,std,2.0\n4,1,1,fair,3.0\n16,1,1,std,4.0\n";
+        let points = Table::parse(samples, &[], "s.csv")
+            .unwrap()
+            .points()
+            .unwrap();
+        let charts = charts(&points);
+        assert_eq!(2, charts.len());
+        assert_eq!("n = 4", charts[0].title);
+        assert_eq!("n_p:n_c", charts[0].x_label);
+        assert_eq!(vec!["1:1", "1:2"], charts[0].categories);
+        assert_eq!(2, charts[0].series[1].points.len());
+        assert_eq!(None, charts[0].series[1].points[1]);
+
+        let svg = charts[0].svg();
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+        assert_eq!(3, svg.matches("class=\"error-bar\"").count());
+        assert_eq!((1.0, 2.0), (tick_step(4.0), tick_step(7.5)));
+    }
+}
@@ -1,35 +1,882 @@
-use std::sync::{Condvar, Mutex};
+use crate::contention::{Contention, ContentionStats};
+use crate::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Condvar, Mutex,
+};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// What the producer/consumer code needs from a semaphore, so it can run on
+// either `Semaphore` or `FairSemaphore`.
+pub trait RawSemaphore: Send + Sync {
+    fn new(count: usize) -> Self
+    where
+        Self: Sized;
+
+    fn available_threads(&self) -> usize;
+
+    fn wait(&self);
+
+    // Takes a permit only if one is available right now.
+    fn try_wait(&self) -> bool;
+
+    // Returns false if no permit showed up within `timeout`.
+    fn wait_timeout(&self, timeout: Duration) -> bool;
+
+    fn signal(&self);
+
+    // Contention counters, for the semaphores that keep them.
+    fn stats(&self) -> ContentionStats {
+        ContentionStats::default()
+    }
+
+    fn acquire(&self) -> Permit<'_, Self>
+    where
+        Self: Sized,
+    {
+        self.wait();
+        Permit { semaphore: self }
+    }
+
+    fn try_acquire(&self) -> Option<Permit<'_, Self>>
+    where
+        Self: Sized,
+    {
+        self.try_wait().then_some(Permit { semaphore: self })
+    }
+
+    fn acquire_timeout(&self, timeout: Duration) -> Option<Permit<'_, Self>>
+    where
+        Self: Sized,
+    {
+        self.wait_timeout(timeout)
+            .then_some(Permit { semaphore: self })
+    }
+}
 
 #[derive(Debug)]
 pub struct Semaphore {
-    counter: Mutex<usize>,
+    counter: Mutex<Counter>,
     cvar: Condvar,
+    contention: Contention,
+}
+
+#[derive(Debug)]
+struct Counter {
+    permits: usize,
+    // Threads blocked in `acquire_many`. Waking a single thread is not
+    // enough while there are any: it could be one asking for more permits
+    // than there are while a thread asking for fewer keeps sleeping.
+    many_waiters: usize,
+}
+
+// A permit taken from a semaphore, given back when dropped. Use `forget`
+// to keep it from being returned, e.g. when a producer hands a slot over
+// to the consumers through another semaphore.
+#[must_use = "the permit is released as soon as it is dropped"]
+#[derive(Debug)]
+pub struct Permit<'a, S: RawSemaphore = Semaphore> {
+    semaphore: &'a S,
 }
 
 impl Semaphore {
     pub fn new(count: usize) -> Self {
         Semaphore {
-            counter: Mutex::new(count),
+            counter: Mutex::new(Counter {
+                permits: count,
+                many_waiters: 0,
+            }),
+            cvar: Condvar::new(),
+            contention: Contention::default(),
+        }
+    }
+
+    pub fn available_threads(&self) -> usize {
+        let count = self.counter.lock().unwrap();
+        count.permits
+    }
+
+    pub fn wait(&self) {
+        let mut waiter = self.contention.waiter();
+        let mut count = self.counter.lock().unwrap();
+        while count.permits == 0 {
+            waiter.spin();
+            count = self.cvar.wait(count).unwrap();
+        }
+        count.permits -= 1;
+        waiter.acquired();
+    }
+
+    pub fn try_wait(&self) -> bool {
+        let mut count = self.counter.lock().unwrap();
+        if count.permits == 0 {
+            return false;
+        }
+        count.permits -= 1;
+        self.contention.waiter().acquired();
+        true
+    }
+
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut waiter = self.contention.waiter();
+        let mut count = self.counter.lock().unwrap();
+        while count.permits == 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            waiter.spin();
+            count = self.cvar.wait_timeout(count, deadline - now).unwrap().0;
+        }
+        count.permits -= 1;
+        waiter.acquired();
+        true
+    }
+
+    pub fn signal(&self) {
+        self.release_many(1);
+    }
+
+    pub fn stats(&self) -> ContentionStats {
+        self.contention.snapshot()
+    }
+
+    // Takes n permits at once, so two threads each asking for several
+    // cannot deadlock holding part of what they need.
+    pub fn acquire_many(&self, n: usize) {
+        let mut waiter = self.contention.waiter();
+        let mut count = self.counter.lock().unwrap();
+        count.many_waiters += 1;
+        while count.permits < n {
+            waiter.spin();
+            count = self.cvar.wait(count).unwrap();
+        }
+        count.many_waiters -= 1;
+        count.permits -= n;
+        waiter.acquired();
+    }
+
+    pub fn release_many(&self, n: usize) {
+        let mut count = self.counter.lock().unwrap();
+        count.permits += n;
+        if count.many_waiters > 0 || n > 1 {
+            self.cvar.notify_all();
+        } else {
+            self.cvar.notify_one();
+        }
+    }
+
+    pub fn acquire(&self) -> Permit<'_> {
+        self.wait();
+        Permit { semaphore: self }
+    }
+
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        self.try_wait().then_some(Permit { semaphore: self })
+    }
+
+    pub fn acquire_timeout(&self, timeout: Duration) -> Option<Permit<'_>> {
+        self.wait_timeout(timeout)
+            .then_some(Permit { semaphore: self })
+    }
+}
+
+// The trait is how generic code reaches the methods above.
+impl RawSemaphore for Semaphore {
+    fn new(count: usize) -> Self {
+        Semaphore::new(count)
+    }
+
+    fn available_threads(&self) -> usize {
+        Semaphore::available_threads(self)
+    }
+
+    fn wait(&self) {
+        Semaphore::wait(self)
+    }
+
+    fn try_wait(&self) -> bool {
+        Semaphore::try_wait(self)
+    }
+
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        Semaphore::wait_timeout(self, timeout)
+    }
+
+    fn signal(&self) {
+        Semaphore::signal(self)
+    }
+
+    fn stats(&self) -> ContentionStats {
+        Semaphore::stats(self)
+    }
+}
+
+impl<S: RawSemaphore> Permit<'_, S> {
+    // Consumes the permit without giving it back to the semaphore.
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl<S: RawSemaphore> Drop for Permit<'_, S> {
+    fn drop(&mut self) {
+        self.semaphore.signal();
+    }
+}
+
+// A semaphore that serves waiters strictly in arrival order. Every waiter
+// takes a ticket and only the one holding the ticket being served may take
+// a permit, so newcomers cannot overtake threads already waiting.
+#[derive(Debug)]
+pub struct FairSemaphore {
+    counter: Mutex<FairCounter>,
+    cvar: Condvar,
+    contention: Contention,
+}
+
+#[derive(Debug)]
+struct FairCounter {
+    permits: usize,
+    next_ticket: u64,
+    // Tickets of the threads waiting, in arrival order. The one in front
+    // is being served.
+    queue: VecDeque<u64>,
+}
+
+impl FairCounter {
+    fn take_ticket(&mut self) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        self.queue.push_back(ticket);
+        ticket
+    }
+
+    fn is_served(&self, ticket: u64) -> bool {
+        self.queue.front() == Some(&ticket)
+    }
+}
+
+impl FairSemaphore {
+    pub fn new(count: usize) -> Self {
+        FairSemaphore {
+            counter: Mutex::new(FairCounter {
+                permits: count,
+                next_ticket: 0,
+                queue: VecDeque::new(),
+            }),
             cvar: Condvar::new(),
+            contention: Contention::default(),
         }
     }
 
     pub fn available_threads(&self) -> usize {
         let count = self.counter.lock().unwrap();
-        *count
+        count.permits
     }
 
     pub fn wait(&self) {
+        let mut waiter = self.contention.waiter();
         let mut count = self.counter.lock().unwrap();
-        while *count <= 0 {
+        let ticket = count.take_ticket();
+        while !count.is_served(ticket) || count.permits == 0 {
+            waiter.spin();
             count = self.cvar.wait(count).unwrap();
         }
-        *count -= 1;
+        self.take_permit(&mut count);
+        waiter.acquired();
+    }
+
+    // Fails while anyone is queued, even if a permit is free: it is
+    // reserved for the first thread in line.
+    pub fn try_wait(&self) -> bool {
+        let mut count = self.counter.lock().unwrap();
+        if !count.queue.is_empty() || count.permits == 0 {
+            return false;
+        }
+        count.permits -= 1;
+        self.contention.waiter().acquired();
+        true
+    }
+
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut waiter = self.contention.waiter();
+        let mut count = self.counter.lock().unwrap();
+        let ticket = count.take_ticket();
+        while !count.is_served(ticket) || count.permits == 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                // Leave the queue. If we were in front, the thread behind
+                // us is served now.
+                let served = count.is_served(ticket);
+                count.queue.retain(|queued| *queued != ticket);
+                if served {
+                    self.cvar.notify_all();
+                }
+                return false;
+            }
+            waiter.spin();
+            count = self.cvar.wait_timeout(count, deadline - now).unwrap().0;
+        }
+        self.take_permit(&mut count);
+        waiter.acquired();
+        true
     }
 
     pub fn signal(&self) {
         let mut count = self.counter.lock().unwrap();
-        *count += 1;
-        self.cvar.notify_one();
+        count.permits += 1;
+        if !count.queue.is_empty() {
+            self.cvar.notify_all();
+        }
+    }
+
+    pub fn stats(&self) -> ContentionStats {
+        self.contention.snapshot()
+    }
+
+    // How many threads are queued for a permit.
+    pub fn waiting(&self) -> usize {
+        self.counter.lock().unwrap().queue.len()
+    }
+
+    // Takes the permit for the ticket being served. Condvars cannot wake a
+    // particular thread, so every waiter is woken up to check whether its
+    // ticket was called.
+    fn take_permit(&self, count: &mut FairCounter) {
+        count.permits -= 1;
+        count.queue.pop_front();
+        if count.permits > 0 && !count.queue.is_empty() {
+            self.cvar.notify_all();
+        }
+    }
+}
+
+// The trait is how generic code reaches the methods above.
+impl RawSemaphore for FairSemaphore {
+    fn new(count: usize) -> Self {
+        FairSemaphore::new(count)
+    }
+
+    fn available_threads(&self) -> usize {
+        FairSemaphore::available_threads(self)
+    }
+
+    fn wait(&self) {
+        FairSemaphore::wait(self)
+    }
+
+    fn try_wait(&self) -> bool {
+        FairSemaphore::try_wait(self)
+    }
+
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        FairSemaphore::wait_timeout(self, timeout)
+    }
+
+    fn signal(&self) {
+        FairSemaphore::signal(self)
+    }
+
+    fn stats(&self) -> ContentionStats {
+        FairSemaphore::stats(self)
+    }
+}
+
+// Lets threads through only once `count_down` was called `count` times.
+// It cannot be reset; once open it stays open.
+#[derive(Debug)]
+pub struct CountDownLatch {
+    count: AtomicUsize,
+    // A turnstile: opening the latch puts a single permit in, and every
+    // thread going through puts it back for the next one.
+    gate: Semaphore,
+}
+
+impl CountDownLatch {
+    pub fn new(count: usize) -> Self {
+        let latch = CountDownLatch {
+            count: AtomicUsize::new(count),
+            gate: Semaphore::new(0),
+        };
+        if count == 0 {
+            latch.gate.signal();
+        }
+        latch
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    // Counting down an open latch does nothing.
+    pub fn count_down(&self) {
+        let counted = self
+            .count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                count.checked_sub(1)
+            });
+        if counted == Ok(1) {
+            self.gate.signal();
+        }
+    }
+
+    pub fn wait(&self) {
+        self.gate.wait();
+        self.gate.signal();
+    }
+}
+
+// A barrier whose number of parties can change between phases: threads
+// `register` to take part and `arrive_and_deregister` to leave. A phase
+// ends once every registered party arrived, and the phases are numbered
+// from 0.
+#[derive(Debug)]
+pub struct Phaser {
+    state: Mutex<PhaseState>,
+    // Threads waiting for the end of a phase sleep on the gate of its
+    // parity. A thread let through cannot get to the same gate again
+    // before everyone waiting for this phase has left it, since the next
+    // phase needs them to arrive too.
+    gates: [Semaphore; 2],
+}
+
+#[derive(Debug)]
+struct PhaseState {
+    phase: usize,
+    parties: usize,
+    arrived: usize,
+    waiting: usize,
+}
+
+impl Phaser {
+    pub fn new(parties: usize) -> Self {
+        Phaser {
+            state: Mutex::new(PhaseState {
+                phase: 0,
+                parties,
+                arrived: 0,
+                waiting: 0,
+            }),
+            gates: [Semaphore::new(0), Semaphore::new(0)],
+        }
+    }
+
+    pub fn phase(&self) -> usize {
+        self.state.lock().unwrap().phase
+    }
+
+    pub fn parties(&self) -> usize {
+        self.state.lock().unwrap().parties
+    }
+
+    // Adds a party to the current phase, which then waits for it too.
+    // Returns the phase it joined.
+    pub fn register(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.parties += 1;
+        state.phase
+    }
+
+    // Waits until every party arrived and returns the new phase.
+    pub fn arrive_and_wait(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.arrived += 1;
+        let phase = state.phase;
+        if state.arrived >= state.parties {
+            self.advance(&mut state);
+            return phase + 1;
+        }
+        state.waiting += 1;
+        drop(state);
+        self.gates[phase % 2].wait();
+        phase + 1
+    }
+
+    // Leaves without waiting, ending the phase if everyone else is there
+    // already. Returns the phase it left in.
+    pub fn arrive_and_deregister(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        assert!(state.parties > 0, "no party left to deregister");
+        state.parties -= 1;
+        let phase = state.phase;
+        if state.arrived > 0 && state.arrived >= state.parties {
+            self.advance(&mut state);
+        }
+        phase
+    }
+
+    fn advance(&self, state: &mut PhaseState) {
+        self.gates[state.phase % 2].release_many(state.waiting);
+        state.phase += 1;
+        state.arrived = 0;
+        state.waiting = 0;
+    }
+}
+
+// A reusable barrier: `wait` blocks until `parties` threads called it,
+// then lets them all go and starts over for the next round.
+#[derive(Debug)]
+pub struct Barrier {
+    phaser: Phaser,
+}
+
+impl Barrier {
+    pub fn new(parties: usize) -> Self {
+        Barrier {
+            phaser: Phaser::new(parties),
+        }
+    }
+
+    pub fn wait(&self) {
+        self.phaser.arrive_and_wait();
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    // Starts a thread that waits on `semaphore` and records `id` once it
+    // gets through. Returns once the thread is in the queue.
+    fn queue_waiter(
+        semaphore: &Arc<FairSemaphore>,
+        order: &Arc<Mutex<Vec<usize>>>,
+        id: usize,
+    ) -> thread::JoinHandle<()> {
+        let queued = semaphore.waiting();
+        let handle = {
+            let semaphore = Arc::clone(semaphore);
+            let order = Arc::clone(order);
+            thread::spawn(move || {
+                semaphore.wait();
+                order.lock().unwrap().push(id);
+            })
+        };
+        while semaphore.waiting() == queued {
+            thread::yield_now();
+        }
+        handle
+    }
+
+    #[test]
+    fn waiters_are_served_in_arrival_order() {
+        let semaphore = Arc::new(FairSemaphore::new(0));
+        let order = Arc::new(Mutex::new(vec![]));
+        let handles = (0..8)
+            .map(|id| queue_waiter(&semaphore, &order, id))
+            .collect::<Vec<_>>();
+
+        for served in 1..=8 {
+            semaphore.signal();
+            // The permit belongs to the head of the queue, a newcomer
+            // cannot grab it even before the head wakes up.
+            assert!(!semaphore.try_wait());
+            while order.lock().unwrap().len() < served {
+                thread::yield_now();
+            }
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!((0..8).collect::<Vec<_>>(), *order.lock().unwrap());
+    }
+
+    #[test]
+    fn abandoned_tickets_are_skipped() {
+        let semaphore = Arc::new(FairSemaphore::new(0));
+        let order = Arc::new(Mutex::new(vec![]));
+        let first = queue_waiter(&semaphore, &order, 0);
+        let impatient = {
+            let semaphore = Arc::clone(&semaphore);
+            thread::spawn(move || semaphore.wait_timeout(Duration::from_millis(10)))
+        };
+        while semaphore.waiting() < 2 && !impatient.is_finished() {
+            thread::yield_now();
+        }
+        let last = queue_waiter(&semaphore, &order, 2);
+
+        assert!(!impatient.join().unwrap());
+        for served in 1..=2 {
+            semaphore.signal();
+            while order.lock().unwrap().len() < served {
+                thread::yield_now();
+            }
+        }
+        first.join().unwrap();
+        last.join().unwrap();
+        assert_eq!(vec![0, 2], *order.lock().unwrap());
+        assert_eq!(0, semaphore.available_threads());
+        assert_eq!(0, semaphore.waiting());
+    }
+
+    // Every thread checks that no more threads than there are permits got
+    // through, and the permits are all back at the end.
+    fn check_permit_count<S: RawSemaphore + 'static>() {
+        let semaphore = Arc::new(S::new(3));
+        let inside = Arc::new(AtomicUsize::new(0));
+        let handles = (0..8)
+            .map(|_| {
+                let semaphore = Arc::clone(&semaphore);
+                let inside = Arc::clone(&inside);
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        let _permit = semaphore.acquire();
+                        assert!(inside.fetch_add(1, Ordering::SeqCst) < 3);
+                        thread::yield_now();
+                        inside.fetch_sub(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(3, semaphore.available_threads());
+    }
+
+    #[test]
+    fn permits_hold_up_under_load() {
+        check_permit_count::<Semaphore>();
+        check_permit_count::<FairSemaphore>();
+    }
+
+    #[test]
+    fn single_and_many_permit_waiters_all_get_through() {
+        let semaphore = Arc::new(Semaphore::new(4));
+        let taken = Arc::new(AtomicUsize::new(0));
+        let handles = (0..8)
+            .map(|i| {
+                let semaphore = Arc::clone(&semaphore);
+                let taken = Arc::clone(&taken);
+                // Half the threads take two permits at a time.
+                let n = 1 + i % 2;
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        if n == 1 {
+                            semaphore.wait();
+                        } else {
+                            semaphore.acquire_many(n);
+                        }
+                        assert!(taken.fetch_add(n, Ordering::SeqCst) + n <= 4);
+                        taken.fetch_sub(n, Ordering::SeqCst);
+                        semaphore.release_many(n);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(4, semaphore.available_threads());
+    }
+
+    #[test]
+    fn latch_opens_after_the_last_count_down() {
+        let latch = CountDownLatch::new(2);
+        let passed = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| {
+                    latch.wait();
+                    passed.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+            latch.count_down();
+            thread::sleep(Duration::from_millis(10));
+            assert_eq!(0, passed.load(Ordering::SeqCst));
+            latch.count_down();
+        });
+        assert_eq!(3, passed.load(Ordering::SeqCst));
+        // An open latch stays open.
+        latch.count_down();
+        assert_eq!(0, latch.count());
+        latch.wait();
+        CountDownLatch::new(0).wait();
+    }
+
+    #[test]
+    fn barrier_keeps_rounds_apart() {
+        let barrier = Barrier::new(4);
+        let arrived = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for round in 1..=50 {
+                        arrived.fetch_add(1, Ordering::SeqCst);
+                        barrier.wait();
+                        // Nobody can be in the next round yet.
+                        assert_eq!(4 * round, arrived.load(Ordering::SeqCst));
+                        barrier.wait();
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn phaser_waits_for_the_parties_it_has() {
+        let phaser = Phaser::new(1);
+        assert_eq!(1, phaser.arrive_and_wait());
+        assert_eq!(1, phaser.register());
+        let phases = thread::scope(|scope| {
+            let helper = scope.spawn(|| {
+                let phase = phaser.arrive_and_wait();
+                phaser.arrive_and_deregister();
+                phase
+            });
+            let first = phaser.arrive_and_wait();
+            // Ends once the helper leaves, whether before or after we arrive.
+            let second = phaser.arrive_and_wait();
+            (helper.join().unwrap(), first, second)
+        });
+        assert_eq!((2, 2, 3), phases);
+        assert_eq!(1, phaser.parties());
+        assert_eq!(3, phaser.phase());
+    }
+
+    #[test]
+    fn try_wait_only_takes_free_permits() {
+        let semaphore = Semaphore::new(2);
+        assert!(semaphore.try_wait());
+        assert!(semaphore.try_wait());
+        assert!(!semaphore.try_wait());
+        assert_eq!(0, semaphore.available_threads());
+        semaphore.signal();
+        assert!(semaphore.try_wait());
+    }
+
+    #[test]
+    fn wait_timeout_gives_up_without_a_permit() {
+        let semaphore = Semaphore::new(0);
+        let started = Instant::now();
+        assert!(!semaphore.wait_timeout(Duration::from_millis(50)));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(0, semaphore.available_threads());
+    }
+
+    #[test]
+    fn wait_timeout_takes_a_permit_signalled_in_time() {
+        let semaphore = Semaphore::new(0);
+        let got_permit = thread::scope(|scope| {
+            let waiter = scope.spawn(|| semaphore.wait_timeout(Duration::from_secs(60)));
+            thread::sleep(Duration::from_millis(20));
+            semaphore.signal();
+            waiter.join().unwrap()
+        });
+        assert!(got_permit);
+        assert_eq!(0, semaphore.available_threads());
+    }
+
+    #[test]
+    fn permits_go_back_however_the_holder_leaves() {
+        fn bail_out(semaphore: &Semaphore) -> Option<()> {
+            let _permit = semaphore.acquire();
+            None?;
+            Some(())
+        }
+
+        let semaphore = Semaphore::new(1);
+        assert!(bail_out(&semaphore).is_none());
+        assert_eq!(1, semaphore.available_threads());
+
+        let panicked = std::panic::catch_unwind(|| {
+            let _permit = semaphore.acquire();
+            panic!("holder failed");
+        });
+        assert!(panicked.is_err());
+        assert_eq!(1, semaphore.available_threads());
+
+        semaphore.acquire().forget();
+        assert_eq!(0, semaphore.available_threads());
+    }
+}
+
+// Run with RUSTFLAGS="--cfg loom" cargo test --release.
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use crate::sync::atomic::{AtomicUsize, Ordering};
+    use crate::sync::thread;
+    use loom::sync::Arc;
+
+    // A signal racing with a thread going to sleep must still wake it up,
+    // or loom reports the deadlock.
+    fn check_no_lost_wakeup<S: RawSemaphore + 'static>() {
+        loom::model(|| {
+            let semaphore = Arc::new(S::new(0));
+            let waiters = (0..2)
+                .map(|_| {
+                    let semaphore = Arc::clone(&semaphore);
+                    thread::spawn(move || semaphore.wait())
+                })
+                .collect::<Vec<_>>();
+            semaphore.signal();
+            semaphore.signal();
+            for waiter in waiters {
+                waiter.join().unwrap();
+            }
+            assert_eq!(0, semaphore.available_threads());
+        });
+    }
+
+    fn check_permit_count<S: RawSemaphore + 'static>() {
+        loom::model(|| {
+            let semaphore = Arc::new(S::new(1));
+            let inside = Arc::new(AtomicUsize::new(0));
+            let enter = {
+                let semaphore = Arc::clone(&semaphore);
+                let inside = Arc::clone(&inside);
+                move || {
+                    let _permit = semaphore.acquire();
+                    assert_eq!(0, inside.fetch_add(1, Ordering::SeqCst));
+                    inside.fetch_sub(1, Ordering::SeqCst);
+                }
+            };
+            let other = thread::spawn(enter.clone());
+            enter();
+            other.join().unwrap();
+            assert_eq!(1, semaphore.available_threads());
+        });
+    }
+
+    #[test]
+    fn signals_are_never_lost() {
+        check_no_lost_wakeup::<Semaphore>();
+        check_no_lost_wakeup::<FairSemaphore>();
+    }
+
+    #[test]
+    fn permits_are_counted_exactly() {
+        check_permit_count::<Semaphore>();
+        check_permit_count::<FairSemaphore>();
+    }
+
+    // Single permits released one at a time must not leave a thread
+    // waiting for two asleep once both are there.
+    #[test]
+    fn many_permit_waiter_is_woken() {
+        loom::model(|| {
+            let semaphore = Arc::new(Semaphore::new(0));
+            let many = {
+                let semaphore = Arc::clone(&semaphore);
+                thread::spawn(move || semaphore.acquire_many(2))
+            };
+            let one = {
+                let semaphore = Arc::clone(&semaphore);
+                thread::spawn(move || semaphore.wait())
+            };
+            for _ in 0..3 {
+                semaphore.signal();
+            }
+            many.join().unwrap();
+            one.join().unwrap();
+            assert_eq!(0, semaphore.available_threads());
+        });
     }
 }
@@ -1,62 +1,649 @@
+use crate::contention::{Contention, ContentionStats, Waiter};
+use crate::lock::{Lock, LockGuard, RawLock, RawTryLock};
+use crate::sync::{
+    atomic::{fence, AtomicBool, AtomicUsize, Ordering},
+    hint,
+    thread::{self, Thread},
+    Mutex,
+};
 use std::{
     cell::UnsafeCell,
-    sync::atomic::{AtomicBool, Ordering},
+    collections::VecDeque,
+    fmt,
+    ops::{Deref, DerefMut},
+    ptr,
+    str::FromStr,
 };
 
-pub struct Spinlock {
+// How many failed attempts the spin-then-yield and spin-then-park
+// strategies make before giving the CPU away.
+const SPIN_LIMIT: u32 = 100;
+// Loom lets the holder run on every spin, so it would never see a waiter
+// park unless waiters park right away.
+const PARK_SPIN_LIMIT: u32 = if cfg!(loom) { 0 } else { SPIN_LIMIT };
+// Bounded exponential backoff waits at most 2^MAX_BACKOFF_SHIFT iterations.
+const MAX_BACKOFF_SHIFT: u32 = 10;
+
+// How a thread waits while someone else holds the lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpinStrategy {
+    // Keep swapping the flag until we get it. Every attempt is a write,
+    // so waiting threads fight over the cache line.
+    TestAndSet,
+    // Only try to swap after a plain load saw the lock free.
+    TestAndTestAndSet,
+    // Test-and-test-and-set telling the CPU we are busy waiting.
+    #[default]
+    SpinHint,
+    // Wait twice as long after every failed attempt, up to a bound.
+    Backoff,
+    // Spin for a while, then yield the CPU between attempts.
+    SpinThenYield,
+    // Spin for a while, then sleep until the holder wakes us up.
+    SpinThenPark,
+}
+
+impl SpinStrategy {
+    pub const ALL: [SpinStrategy; 6] = [
+        SpinStrategy::TestAndSet,
+        SpinStrategy::TestAndTestAndSet,
+        SpinStrategy::SpinHint,
+        SpinStrategy::Backoff,
+        SpinStrategy::SpinThenYield,
+        SpinStrategy::SpinThenPark,
+    ];
+}
+
+impl fmt::Display for SpinStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::TestAndSet => "tas",
+            Self::TestAndTestAndSet => "ttas",
+            Self::SpinHint => "hint",
+            Self::Backoff => "backoff",
+            Self::SpinThenYield => "yield",
+            Self::SpinThenPark => "park",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for SpinStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|strategy| strategy.to_string() == s)
+            .ok_or_else(|| format!("unknown spin strategy '{s}'"))
+    }
+}
+
+// A spinlock protecting a value of type T, reached through a guard as
+// described on `Lock`.
+pub type Spinlock<T> = Lock<RawSpinlock, T>;
+pub type SpinlockGuard<'a, T> = LockGuard<'a, RawSpinlock, T>;
+
+// The test-and-set flag behind `Spinlock`.
+#[derive(Debug, Default)]
+pub struct RawSpinlock {
     locked: AtomicBool,
-    data: UnsafeCell<i32>,
+    strategy: SpinStrategy,
+    // Threads sleeping in `SpinThenPark`, woken up one at a time on release.
+    parked: AtomicUsize,
+    waiters: Mutex<VecDeque<Thread>>,
+    contention: Contention,
+}
+
+impl<T> Spinlock<T> {
+    pub fn with_strategy(initial_value: T, strategy: SpinStrategy) -> Self {
+        Self::with_raw(RawSpinlock::with_strategy(strategy), initial_value)
+    }
+
+    pub fn strategy(&self) -> SpinStrategy {
+        self.raw().strategy
+    }
+
+    pub fn stats(&self) -> ContentionStats {
+        self.raw().contention.snapshot()
+    }
+}
+
+impl RawSpinlock {
+    pub fn with_strategy(strategy: SpinStrategy) -> Self {
+        Self {
+            strategy,
+            ..Self::default()
+        }
+    }
+
+    // A failed compare-exchange leaves the flag alone, so only the thread
+    // that gets the lock writes to it.
+    fn try_acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    // Writes the flag even when the lock is taken, like the textbook
+    // test-and-set instruction.
+    fn test_and_set(&self) -> bool {
+        !self.locked.swap(true, Ordering::Acquire)
+    }
+
+    #[cfg_attr(loom, allow(clippy::reversed_empty_ranges))]
+    fn lock_parking(&self, waiter: &mut Waiter) {
+        loop {
+            for _ in 0..PARK_SPIN_LIMIT {
+                if !self.locked.load(Ordering::Relaxed) && self.try_acquire() {
+                    return;
+                }
+                waiter.spin();
+                hint::spin_loop();
+            }
+
+            let me = thread::current();
+            {
+                let mut waiters = self.waiters.lock().unwrap();
+                waiters.push_back(me.clone());
+                self.parked.store(waiters.len(), Ordering::Relaxed);
+            }
+            // Pairs with the fence in `unlock`: either the holder sees us
+            // in the queue, or we see the lock free here.
+            fence(Ordering::SeqCst);
+            let acquired = self.try_acquire();
+            if !acquired {
+                thread::park();
+            }
+            // Whoever unparked us already took us out of the queue, but a
+            // spurious wakeup or the retry above may leave us in it.
+            let mut waiters = self.waiters.lock().unwrap();
+            waiters.retain(|waiter| waiter.id() != me.id());
+            self.parked.store(waiters.len(), Ordering::Relaxed);
+            drop(waiters);
+            if acquired {
+                return;
+            }
+        }
+    }
+}
+
+unsafe impl RawLock for RawSpinlock {
+    type Token = ();
+
+    fn lock(&self) {
+        // Every failed attempt and every turn of an inner loop is a spin.
+        let mut waiter = self.contention.waiter();
+        match self.strategy {
+            SpinStrategy::TestAndSet => {
+                while !self.test_and_set() {
+                    waiter.spin();
+                }
+            }
+            SpinStrategy::TestAndTestAndSet => {
+                while !self.test_and_set() {
+                    waiter.spin();
+                    // Leaving out the hint is what sets this apart from `SpinHint`.
+                    #[allow(clippy::missing_spin_loop)]
+                    while self.locked.load(Ordering::Relaxed) {
+                        waiter.spin();
+                    }
+                }
+            }
+            SpinStrategy::SpinHint => {
+                while !self.try_acquire() {
+                    waiter.spin();
+                    while self.locked.load(Ordering::Relaxed) {
+                        waiter.spin();
+                        hint::spin_loop();
+                    }
+                }
+            }
+            SpinStrategy::Backoff => {
+                let mut shift = 0;
+                while !self.try_acquire() {
+                    waiter.spin();
+                    for _ in 0..1 << shift {
+                        waiter.spin();
+                        hint::spin_loop();
+                    }
+                    shift = (shift + 1).min(MAX_BACKOFF_SHIFT);
+                }
+            }
+            SpinStrategy::SpinThenYield => {
+                let mut attempts = 0;
+                while !self.try_acquire() {
+                    waiter.spin();
+                    if attempts < SPIN_LIMIT {
+                        attempts += 1;
+                        hint::spin_loop();
+                    } else {
+                        thread::yield_now();
+                    }
+                }
+            }
+            SpinStrategy::SpinThenPark => self.lock_parking(&mut waiter),
+        }
+        waiter.acquired();
+    }
+
+    unsafe fn unlock(&self, _: ()) {
+        self.locked.store(false, Ordering::Release);
+        if self.strategy != SpinStrategy::SpinThenPark {
+            return;
+        }
+        fence(Ordering::SeqCst);
+        if self.parked.load(Ordering::Relaxed) > 0 {
+            let mut waiters = self.waiters.lock().unwrap();
+            if let Some(waiter) = waiters.pop_front() {
+                self.parked.store(waiters.len(), Ordering::Relaxed);
+                waiter.unpark();
+            }
+        }
+    }
+
+    fn stats(&self) -> Option<ContentionStats> {
+        Some(self.contention.snapshot())
+    }
+}
+
+unsafe impl RawTryLock for RawSpinlock {
+    fn try_lock(&self) -> Option<()> {
+        let acquired = match self.strategy {
+            SpinStrategy::TestAndSet | SpinStrategy::TestAndTestAndSet => self.test_and_set(),
+            _ => self.try_acquire(),
+        };
+        if acquired {
+            self.contention.waiter().acquired();
+        }
+        acquired.then_some(())
+    }
+}
+
+// Who gets in first when readers and writers are both waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RwPreference {
+    // New readers keep coming in while a writer waits. Writers may starve
+    // under a steady stream of readers.
+    Readers,
+    // New readers hold back while a writer waits. A thread that tries to
+    // read again while already holding a read guard can deadlock.
+    #[default]
+    Writers,
+}
+
+// Writer bit in `RwSpinlock::state`, the rest counts readers.
+const WRITER: usize = 1;
+const READER: usize = 2;
+
+// A spinlock that lets many readers in at once, or a single writer.
+pub struct RwSpinlock<T> {
+    state: AtomicUsize,
+    writers_waiting: AtomicUsize,
+    preference: RwPreference,
+    data: UnsafeCell<T>,
+}
+
+pub struct RwSpinlockReadGuard<'a, T> {
+    lock: &'a RwSpinlock<T>,
+}
+
+pub struct RwSpinlockWriteGuard<'a, T> {
+    lock: &'a RwSpinlock<T>,
 }
 
-impl Spinlock {
-    pub fn new(initial_value: i32) -> Self {
+impl<T> RwSpinlock<T> {
+    pub fn new(initial_value: T) -> Self {
+        Self::with_preference(initial_value, RwPreference::default())
+    }
+
+    pub fn with_preference(initial_value: T, preference: RwPreference) -> Self {
         Self {
-            locked: AtomicBool::new(false),
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            preference,
             data: UnsafeCell::new(initial_value),
         }
     }
 
-    pub fn acquire(&self) {
-        while self.locked.swap(true, Ordering::SeqCst) {}
+    pub fn preference(&self) -> RwPreference {
+        self.preference
     }
 
-    pub fn release(&self) {
-        self.locked.store(false, Ordering::SeqCst);
+    pub fn read(&self) -> RwSpinlockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            hint::spin_loop();
+        }
     }
 
-    pub fn get(&self) -> &mut i32 {
-        unsafe { &mut *self.data.get() }
+    // Only fails if a writer holds the lock, or is waiting for it when
+    // writers are preferred; other readers are never a reason to give up.
+    pub fn try_read(&self) -> Option<RwSpinlockReadGuard<'_, T>> {
+        if self.preference == RwPreference::Writers
+            && self.writers_waiting.load(Ordering::Relaxed) > 0
+        {
+            return None;
+        }
+        let mut state = self.state.load(Ordering::Relaxed);
+        while state & WRITER == 0 {
+            match self.state.compare_exchange_weak(
+                state,
+                state + READER,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(RwSpinlockReadGuard { lock: self }),
+                Err(current) => state = current,
+            }
+        }
+        None
     }
-    pub fn get_mut(&mut self) -> &mut i32 {
+
+    pub fn write(&self) -> RwSpinlockWriteGuard<'_, T> {
+        self.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        while !self.try_acquire_write() {
+            while self.state.load(Ordering::Relaxed) != 0 {
+                hint::spin_loop();
+            }
+        }
+        self.writers_waiting.fetch_sub(1, Ordering::Relaxed);
+        RwSpinlockWriteGuard { lock: self }
+    }
+
+    pub fn try_write(&self) -> Option<RwSpinlockWriteGuard<'_, T>> {
+        self.try_acquire_write()
+            .then_some(RwSpinlockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
         self.data.get_mut()
     }
 
-    pub fn set(&self, int: i32) -> Result<(), ()> {
-        if self.locked.load(Ordering::SeqCst) {
-            let d = self.data.get();
-            unsafe {
-                *d = int;
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    fn try_acquire_write(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+// Readers share &T between threads, so T must be Sync as well.
+unsafe impl<T: Send + Sync> Sync for RwSpinlock<T> {}
+unsafe impl<T: Send> Send for RwSpinlock<T> {}
+
+impl<T> Deref for RwSpinlockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwSpinlockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+impl<T> Deref for RwSpinlockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwSpinlockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwSpinlockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // Readers never touch the state while the writer bit is set.
+        self.lock.state.store(0, Ordering::Release);
+    }
+}
+
+// A sequence lock for small Copy values that are read far more often than
+// written. Readers never write to shared memory: they copy the value and
+// retry if a writer was active meanwhile, which they notice because the
+// sequence number is odd during a write and changes after it.
+//
+// Copying the value while it may be written is a data race as far as the
+// Rust memory model is concerned; like every seqlock we rely on volatile
+// reads and on throwing away torn copies.
+pub struct SeqLock<T: Copy> {
+    seq: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+impl<T: Copy> SeqLock<T> {
+    pub fn new(initial_value: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            data: UnsafeCell::new(initial_value),
+        }
+    }
+
+    pub fn read(&self) -> T {
+        loop {
+            let before = self.seq.load(Ordering::Acquire);
+            if before & 1 == 1 {
+                hint::spin_loop();
+                continue;
+            }
+            let value = unsafe { ptr::read_volatile(self.data.get()) };
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == before {
+                return value;
             }
-            return Ok(());
-        } else {
-            return Err(());
         }
     }
+
+    pub fn write(&self, value: T) {
+        self.update(|data| *data = value);
+    }
+
+    // Writers exclude each other by being the one to make `seq` odd.
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        loop {
+            if seq & 1 == 0 {
+                match self.seq.compare_exchange_weak(
+                    seq,
+                    seq + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => seq = current,
+                }
+            } else {
+                hint::spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+            }
+        }
+        // Keeps the data writes below from moving before the odd number.
+        fence(Ordering::Release);
+        let mut value = unsafe { ptr::read_volatile(self.data.get()) };
+        f(&mut value);
+        unsafe { ptr::write_volatile(self.data.get(), value) };
+        self.seq.store(seq + 2, Ordering::Release);
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
 }
 
-unsafe impl Sync for Spinlock {}
-unsafe impl Send for Spinlock {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn every_strategy_keeps_threads_out_of_each_other() {
+        for strategy in SpinStrategy::ALL {
+            let lock = Arc::new(Spinlock::with_strategy(0, strategy));
+            let inside = Arc::new(AtomicUsize::new(0));
+            let handles = (0..4)
+                .map(|_| {
+                    let lock = Arc::clone(&lock);
+                    let inside = Arc::clone(&inside);
+                    thread::spawn(move || {
+                        for _ in 0..1_000 {
+                            let mut count = lock.lock();
+                            assert_eq!(0, inside.fetch_add(1, Ordering::SeqCst));
+                            *count += 1;
+                            inside.fetch_sub(1, Ordering::SeqCst);
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            assert_eq!(4_000, *lock.lock(), "{strategy} lost updates");
+            let _held = lock.lock();
+            assert!(lock.try_lock().is_none());
+        }
+    }
 
-impl std::ops::Deref for Spinlock {
-    type Target = i32;
+    #[cfg(feature = "stats")]
+    #[test]
+    fn every_acquisition_is_counted() {
+        for strategy in SpinStrategy::ALL {
+            let lock = Spinlock::with_strategy(0, strategy);
+            thread::scope(|scope| {
+                for _ in 0..4 {
+                    scope.spawn(|| {
+                        for _ in 0..100 {
+                            *lock.lock() += 1;
+                        }
+                    });
+                }
+            });
+            assert!(lock.try_lock().is_some());
+            let stats = lock.stats();
+            assert_eq!(401, stats.acquisitions, "{strategy}");
+            // Whoever holds the lock is not waiting for it.
+            assert!(stats.max_queue <= 3, "{strategy}");
+        }
+    }
 
-    fn deref(&self) -> &i32 {
-        unsafe { &*self.data.get() }
+    #[test]
+    fn readers_never_see_half_done_writes() {
+        let rw = Arc::new(RwSpinlock::new([0_u64; 4]));
+        let seq = Arc::new(SeqLock::new([0_u64; 4]));
+        let writers = (0..2)
+            .map(|_| {
+                let rw = Arc::clone(&rw);
+                let seq = Arc::clone(&seq);
+                thread::spawn(move || {
+                    for _ in 0..500 {
+                        rw.write().iter_mut().for_each(|field| *field += 1);
+                        seq.update(|record| record.iter_mut().for_each(|field| *field += 1));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let readers = (0..2)
+            .map(|_| {
+                let rw = Arc::clone(&rw);
+                let seq = Arc::clone(&seq);
+                thread::spawn(move || {
+                    for _ in 0..500 {
+                        let record = *rw.read();
+                        assert!(record.iter().all(|field| *field == record[0]));
+                        let record = seq.read();
+                        assert!(record.iter().all(|field| *field == record[0]));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().unwrap();
+        }
+        assert_eq!([1_000; 4], *rw.read());
+        assert_eq!([1_000; 4], seq.read());
     }
 }
 
-impl std::ops::DerefMut for Spinlock {
-    fn deref_mut(&mut self) -> &mut i32 {
-        unsafe { &mut *self.data.get() }
+// Run with RUSTFLAGS="--cfg loom" cargo test --release. Loom does not order
+// a failed swap after the release it raced with, so a thread spinning on
+// `test_and_set` may read its own write forever and a contended `lock`
+// never finishes. Those two strategies race with `try_lock` instead.
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use loom::sync::Arc;
+
+    #[test]
+    fn contended_lock_is_exclusive() {
+        for strategy in [
+            SpinStrategy::SpinHint,
+            SpinStrategy::Backoff,
+            SpinStrategy::SpinThenYield,
+            SpinStrategy::SpinThenPark,
+        ] {
+            loom::model(move || {
+                let mut lock = Arc::new(Spinlock::with_strategy(0, strategy));
+                let inside = Arc::new(AtomicUsize::new(0));
+                let increment = {
+                    let lock = Arc::clone(&lock);
+                    let inside = Arc::clone(&inside);
+                    move || {
+                        let mut count = lock.lock();
+                        assert_eq!(0, inside.fetch_add(1, Ordering::SeqCst));
+                        *count += 1;
+                        inside.fetch_sub(1, Ordering::SeqCst);
+                    }
+                };
+                let other = thread::spawn(increment.clone());
+                increment();
+                other.join().unwrap();
+                drop(increment);
+                assert_eq!(2, *Arc::get_mut(&mut lock).unwrap().get_mut());
+            });
+        }
+    }
+
+    #[test]
+    fn test_and_set_is_exclusive() {
+        for strategy in [SpinStrategy::TestAndSet, SpinStrategy::TestAndTestAndSet] {
+            loom::model(move || {
+                let mut lock = Arc::new(Spinlock::with_strategy(0, strategy));
+                let inside = Arc::new(AtomicUsize::new(0));
+                let increment = {
+                    let lock = Arc::clone(&lock);
+                    let inside = Arc::clone(&inside);
+                    move || {
+                        let Some(mut count) = lock.try_lock() else {
+                            return 0;
+                        };
+                        assert_eq!(0, inside.fetch_add(1, Ordering::SeqCst));
+                        *count += 1;
+                        inside.fetch_sub(1, Ordering::SeqCst);
+                        1
+                    }
+                };
+                let other = thread::spawn(increment.clone());
+                let got_in = increment() + other.join().unwrap();
+                drop(increment);
+                assert!(got_in > 0, "both threads were turned away");
+                assert_eq!(got_in, *Arc::get_mut(&mut lock).unwrap().get_mut());
+            });
+        }
     }
 }
@@ -0,0 +1,7 @@
+// The primitives `Spinlock` and the semaphores are built on. Building with
+// `RUSTFLAGS="--cfg loom"` swaps in loom's, so the loom tests explore every
+// interleaving of their threads instead of whichever the scheduler picks.
+#[cfg(loom)]
+pub(crate) use loom::{hint, sync::atomic, sync::Condvar, sync::Mutex, thread};
+#[cfg(not(loom))]
+pub(crate) use std::{hint, sync::atomic, sync::Condvar, sync::Mutex, thread};
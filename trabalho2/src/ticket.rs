@@ -0,0 +1,138 @@
+use crate::lock::{Lock, RawLock, RawTryLock};
+use crate::sync::{
+    atomic::{AtomicUsize, Ordering},
+    hint,
+};
+
+// Threads take a number on arrival and wait until it is called, so the
+// lock is handed over in arrival order. Every waiter spins on the same
+// `now_serving` counter, which gets expensive with many threads.
+pub type TicketLock<T> = Lock<RawTicketLock, T>;
+
+#[derive(Debug, Default)]
+pub struct RawTicketLock {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+}
+
+unsafe impl RawLock for RawTicketLock {
+    type Token = ();
+
+    fn lock(&self) {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            hint::spin_loop();
+        }
+    }
+
+    unsafe fn unlock(&self, _: ()) {
+        // Only the holder writes `now_serving`, so this cannot race.
+        let next = self.now_serving.load(Ordering::Relaxed).wrapping_add(1);
+        self.now_serving.store(next, Ordering::Release);
+    }
+}
+
+unsafe impl RawTryLock for RawTicketLock {
+    // Takes a ticket only if it would be served right away.
+    fn try_lock(&self) -> Option<()> {
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        self.next_ticket
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| ())
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn keeps_threads_out_of_each_other() {
+        let lock = Arc::new(TicketLock::new(0));
+        let inside = Arc::new(AtomicUsize::new(0));
+        let handles = (0..4)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                let inside = Arc::clone(&inside);
+                thread::spawn(move || {
+                    for _ in 0..1_000 {
+                        let mut count = lock.lock();
+                        assert_eq!(0, inside.fetch_add(1, Ordering::SeqCst));
+                        *count += 1;
+                        inside.fetch_sub(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(4_000, *lock.lock());
+    }
+
+    #[test]
+    fn try_lock_only_succeeds_when_free() {
+        let lock = TicketLock::new(0);
+        let held = lock.lock();
+        assert!(lock.try_lock().is_none());
+        drop(held);
+        *lock.try_lock().unwrap() += 1;
+        assert_eq!(1, *lock.lock());
+    }
+
+    #[test]
+    fn hands_the_lock_over_in_arrival_order() {
+        let lock = TicketLock::new(Vec::new());
+        let held = lock.lock();
+        thread::scope(|scope| {
+            for id in 0..3 {
+                let lock = &lock;
+                scope.spawn(move || lock.lock().push(id));
+                // Let the thread take its ticket before the next one arrives.
+                while lock.raw().next_ticket.load(Ordering::Relaxed) != id + 2 {
+                    thread::yield_now();
+                }
+            }
+            drop(held);
+        });
+        assert_eq!([0, 1, 2], lock.lock()[..]);
+    }
+}
+
+// Run with RUSTFLAGS="--cfg loom" cargo test --release.
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use crate::sync::thread;
+    use loom::sync::Arc;
+
+    #[test]
+    fn contended_lock_is_exclusive() {
+        loom::model(|| {
+            let lock = Arc::new(TicketLock::new(0));
+            let inside = Arc::new(AtomicUsize::new(0));
+            let increment = {
+                let lock = Arc::clone(&lock);
+                let inside = Arc::clone(&inside);
+                move || {
+                    let mut count = lock.lock();
+                    assert_eq!(0, inside.fetch_add(1, Ordering::SeqCst));
+                    *count += 1;
+                    inside.fetch_sub(1, Ordering::SeqCst);
+                }
+            };
+            let other = thread::spawn(increment.clone());
+            increment();
+            other.join().unwrap();
+            assert_eq!(2, *lock.lock());
+            assert!(lock.try_lock().is_some());
+        });
+    }
+}
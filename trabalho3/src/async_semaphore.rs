@@ -0,0 +1,351 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+// A semaphore for async code. Instead of blocking the thread like
+// trabalho2's `Semaphore`, `acquire` returns a future that completes once a
+// permit is available, so tasks waiting for it do not stall the runtime.
+//
+// Waiters are served in arrival order: released permits go straight to the
+// first queued future and newcomers only get some when nobody waits. A
+// future asking for several permits holds up the ones behind it until it
+// gets all of them, so it cannot starve.
+#[derive(Debug)]
+pub struct AsyncSemaphore {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    permits: usize,
+    next_id: u64,
+    waiters: VecDeque<Waiter>,
+    // Waiters that were handed their permits but have not been polled since.
+    granted: BTreeSet<u64>,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    wanted: usize,
+    waker: Waker,
+}
+
+impl State {
+    // Hands permits to the queued futures in order, for as long as the one
+    // in front can have all it asked for. Returns the wakers to call once
+    // the lock is released.
+    fn grant(&mut self) -> Vec<Waker> {
+        let mut woken = vec![];
+        while let Some(waiter) = self.waiters.front() {
+            if waiter.wanted > self.permits {
+                break;
+            }
+            let waiter = self.waiters.pop_front().unwrap();
+            self.permits -= waiter.wanted;
+            self.granted.insert(waiter.id);
+            woken.push(waiter.waker);
+        }
+        woken
+    }
+}
+
+// Future returned by `AsyncSemaphore::acquire` and `acquire_many`.
+//
+// Dropping it before it completes gives up its place in the queue. If the
+// permits were already handed to it, they go back to the semaphore and on
+// to the next waiter, so wrapping it in a timeout or a `select!` never
+// loses permits.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct Acquire<'a> {
+    semaphore: &'a AsyncSemaphore,
+    wanted: usize,
+    // Our place in the queue once we had to wait.
+    id: Option<u64>,
+    done: bool,
+}
+
+// Same as `Permit` for the blocking semaphore: the permits are released on
+// drop, unless `forget` is called.
+#[must_use = "the permit is released as soon as it is dropped"]
+#[derive(Debug)]
+pub struct AsyncPermit<'a> {
+    semaphore: &'a AsyncSemaphore,
+    permits: usize,
+}
+
+impl AsyncSemaphore {
+    pub fn new(count: usize) -> Self {
+        AsyncSemaphore {
+            state: Mutex::new(State {
+                permits: count,
+                next_id: 0,
+                waiters: VecDeque::new(),
+                granted: BTreeSet::new(),
+            }),
+        }
+    }
+
+    pub fn available_threads(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    // Takes n permits at once, so two tasks each asking for several cannot
+    // deadlock holding part of what they need.
+    pub fn acquire_many(&self, n: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            wanted: n,
+            id: None,
+            done: false,
+        }
+    }
+
+    // Gives up after `timeout`, without taking anything.
+    pub async fn acquire_timeout(&self, timeout: Duration) -> Option<AsyncPermit<'_>> {
+        tokio::time::timeout(timeout, self.acquire()).await.ok()
+    }
+
+    // Fails while futures are queued, the next permit is theirs.
+    pub fn try_acquire(&self) -> Option<AsyncPermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, n: usize) -> Option<AsyncPermit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if state.permits < n || !state.waiters.is_empty() {
+            return None;
+        }
+        state.permits -= n;
+        Some(AsyncPermit {
+            semaphore: self,
+            permits: n,
+        })
+    }
+
+    pub fn signal(&self) {
+        self.release_many(1);
+    }
+
+    pub fn release_many(&self, n: usize) {
+        let woken = {
+            let mut state = self.state.lock().unwrap();
+            state.permits += n;
+            state.grant()
+        };
+        // Woken outside the lock, the task may run on another thread and
+        // poll right away.
+        for waker in woken {
+            waker.wake();
+        }
+    }
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = AsyncPermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        let wanted = self.wanted;
+        let mut state = semaphore.state.lock().unwrap();
+        let ready = match self.id {
+            None if state.permits >= wanted && state.waiters.is_empty() => {
+                state.permits -= wanted;
+                true
+            }
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.waiters.push_back(Waiter {
+                    id,
+                    wanted,
+                    waker: cx.waker().clone(),
+                });
+                self.id = Some(id);
+                false
+            }
+            Some(id) if state.granted.remove(&id) => true,
+            Some(id) => {
+                // We may have been moved to another task since the last poll.
+                if let Some(waiter) = state.waiters.iter_mut().find(|w| w.id == id) {
+                    waiter.waker.clone_from(cx.waker());
+                }
+                false
+            }
+        };
+        drop(state);
+        if ready {
+            self.done = true;
+            Poll::Ready(AsyncPermit {
+                semaphore,
+                permits: wanted,
+            })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let id = match self.id {
+            Some(id) if !self.done => id,
+            _ => return,
+        };
+        let woken = {
+            let mut state = self.semaphore.state.lock().unwrap();
+            if state.granted.remove(&id) {
+                state.permits += self.wanted;
+            } else {
+                state.waiters.retain(|w| w.id != id);
+            }
+            // Either way the futures behind us may be able to go now.
+            state.grant()
+        };
+        for waker in woken {
+            waker.wake();
+        }
+    }
+}
+
+impl AsyncPermit<'_> {
+    // Keeps the permits taken: dropping the permit no longer releases them,
+    // only a later `signal` or `release_many` gives them back.
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for AsyncPermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release_many(self.permits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn limits_concurrent_tasks() {
+        let semaphore = Arc::new(AsyncSemaphore::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let tasks = (0..16)
+            .map(|_| {
+                let semaphore = Arc::clone(&semaphore);
+                let running = Arc::clone(&running);
+                let most = Arc::clone(&most);
+                tokio::spawn(async move {
+                    let _permit = semaphore.acquire().await;
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(2, most.load(Ordering::SeqCst));
+        assert_eq!(2, semaphore.available_threads());
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiters_do_not_lose_permits() {
+        let semaphore = AsyncSemaphore::new(1);
+        let permit = semaphore.acquire().await;
+        let timed_out = tokio::time::timeout(Duration::from_millis(10), semaphore.acquire()).await;
+        assert!(timed_out.is_err());
+        drop(permit);
+        assert_eq!(1, semaphore.available_threads());
+        assert!(semaphore.try_acquire().is_some());
+    }
+
+    #[test]
+    fn granted_permit_moves_on_when_future_is_dropped() {
+        let semaphore = AsyncSemaphore::new(0);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut first = Box::pin(semaphore.acquire());
+        let mut second = Box::pin(semaphore.acquire());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        // Nobody can barge in front of the queue.
+        semaphore.signal();
+        assert!(semaphore.try_acquire().is_none());
+
+        // The permit was handed to `first`, which goes away without taking it.
+        drop(first);
+        match second.as_mut().poll(&mut cx) {
+            Poll::Ready(permit) => permit.forget(),
+            Poll::Pending => panic!("the permit was lost with the dropped future"),
+        }
+        assert_eq!(0, semaphore.available_threads());
+    }
+
+    #[test]
+    fn many_permits_are_taken_and_given_back_together() {
+        let semaphore = AsyncSemaphore::new(3);
+        let mut cx = Context::from_waker(Waker::noop());
+        let one = semaphore.try_acquire().unwrap();
+        assert!(semaphore.try_acquire_many(3).is_none());
+
+        let mut three = Box::pin(semaphore.acquire_many(3));
+        assert!(three.as_mut().poll(&mut cx).is_pending());
+        // The two free permits wait for the future in front, which needs three.
+        assert!(semaphore.try_acquire().is_none());
+        drop(one);
+        let three = match three.as_mut().poll(&mut cx) {
+            Poll::Ready(permit) => permit,
+            Poll::Pending => panic!("three permits were free"),
+        };
+        assert_eq!(0, semaphore.available_threads());
+        drop(three);
+        assert_eq!(3, semaphore.available_threads());
+        assert!(semaphore.try_acquire_many(3).is_some());
+    }
+
+    #[test]
+    fn dropping_a_large_request_lets_smaller_ones_through() {
+        let semaphore = AsyncSemaphore::new(1);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut large = Box::pin(semaphore.acquire_many(2));
+        let mut small = Box::pin(semaphore.acquire());
+        assert!(large.as_mut().poll(&mut cx).is_pending());
+        assert!(small.as_mut().poll(&mut cx).is_pending());
+
+        drop(large);
+        assert!(small.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[tokio::test]
+    async fn acquire_timeout_waits_for_a_permit_in_time() {
+        let semaphore = Arc::new(AsyncSemaphore::new(0));
+        assert!(semaphore
+            .acquire_timeout(Duration::from_millis(10))
+            .await
+            .is_none());
+
+        let signaller = {
+            let semaphore = Arc::clone(&semaphore);
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                semaphore.signal();
+            })
+        };
+        let permit = semaphore.acquire_timeout(Duration::from_secs(60)).await;
+        assert!(permit.is_some());
+        signaller.await.unwrap();
+    }
+}
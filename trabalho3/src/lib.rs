@@ -1,3 +1,4 @@
+pub mod async_semaphore;
 pub mod messages;
 pub mod process;
 pub mod scheduler;
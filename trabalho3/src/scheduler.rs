@@ -1,8 +1,4 @@
-use std::{
-    error::Error,
-    io,
-    sync::{atomic::AtomicBool, Arc},
-};
+use std::{error::Error, io, sync::Arc};
 
 use tokio::{
     net::{TcpListener, TcpStream},
@@ -11,12 +7,14 @@ use tokio::{
 
 use log::{debug, error, info, warn};
 
+use crate::async_semaphore::AsyncSemaphore;
 use crate::messages::{Message, MessageType};
 
 pub struct Scheduler {
     queue: Arc<Mutex<Vec<u8>>>,
     listener: TcpListener,
-    is_busy: Arc<AtomicBool>,
+    // One permit: the process holding it is in the critical region.
+    region: Arc<AsyncSemaphore>,
 }
 
 impl Scheduler {
@@ -27,7 +25,7 @@ impl Scheduler {
             listener: TcpListener::bind(addr)
                 .await
                 .expect("Could not open TCPListener."),
-            is_busy: Arc::new(AtomicBool::new(false)),
+            region: Arc::new(AsyncSemaphore::new(1)),
         }
     }
 
@@ -37,9 +35,9 @@ impl Scheduler {
                 Ok((stream, addr)) => {
                     info!("[SCHEDULER] Starting connection with {}", addr);
                     let queue = Arc::clone(&self.queue);
-                    let occupied = Arc::clone(&self.is_busy);
+                    let region = Arc::clone(&self.region);
                     tokio::task::spawn(async {
-                        handle_connection(stream, queue, occupied).await.unwrap();
+                        handle_connection(stream, queue, region).await.unwrap();
                     });
                 }
                 Err(e) => {
@@ -54,7 +52,7 @@ impl Scheduler {
 async fn handle_connection(
     stream: TcpStream,
     queue_arc: Arc<Mutex<Vec<u8>>>,
-    occupied_arc: Arc<AtomicBool>,
+    region: Arc<AsyncSemaphore>,
 ) -> Result<(), Box<dyn Error>> {
     loop {
         stream.readable().await?;
@@ -71,7 +69,6 @@ async fn handle_connection(
                 match message.message_type() {
                     MessageType::Request => {
                         let grant_message = Message::new(0, MessageType::Grant);
-                        let occupied = Arc::clone(&occupied_arc);
                         info!(
                             "[SCHEDULER] [HANDLER] Waiting for availability on process #{}",
                             message.sender
@@ -79,8 +76,11 @@ async fn handle_connection(
                         let mutex = Arc::clone(&queue_arc);
                         let mut connections = mutex.lock().await;
                         connections.push(message.sender);
-                        while occupied.swap(true, std::sync::atomic::Ordering::SeqCst) {}
                         drop(connections);
+                        // The permit is given back when the Release message
+                        // arrives, possibly after this handler went back to
+                        // reading.
+                        region.acquire().await.forget();
                         info!(
                             "[SCHEDULER] [HANDLER] Granting access to process #{}.",
                             message.sender
@@ -96,8 +96,7 @@ async fn handle_connection(
                             "[SCHEDULER] [HANDLER] Releasing access to process #{}.",
                             message.sender
                         );
-                        let occupied = Arc::clone(&occupied_arc);
-                        occupied.swap(false, std::sync::atomic::Ordering::SeqCst);
+                        region.signal();
                         let mutex = Arc::clone(&queue_arc);
                         let mut connections = mutex.lock().await;
                         *connections = connections[1..].to_vec();